@@ -0,0 +1,111 @@
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+/// Key of the application in the manifest SteamVR starts the overlay from.
+pub const APP_KEY: &str = "org.mon-ouie.mpris-openvr-overlay";
+
+/// openvr_sys2 does not generate IVRApplications, so it is used through the
+/// C function table OpenVR hands out for it.
+const FN_TABLE_VERSION: &[u8] = b"FnTable:IVRApplications_007\0";
+
+/// `EVRApplicationError_VRApplicationError_None`.
+const APPLICATION_ERROR_NONE: u32 = 0;
+
+extern "C" {
+    fn VR_GetGenericInterface(interface_version: *const c_char,
+                              error: *mut openvr_sys2::EVRInitError) -> *mut c_void;
+}
+
+/// Prefix of `VR_IVRApplications_FnTable` from `openvr_capi.h`, up to the
+/// last function used here.
+#[repr(C)]
+struct FnTable {
+    add_application_manifest: unsafe extern "C" fn(*const c_char, bool) -> u32,
+    remove_application_manifest: unsafe extern "C" fn(*const c_char) -> u32,
+    is_application_installed: unsafe extern "C" fn(*const c_char) -> bool,
+    _launch_functions: [*const c_void; 10],
+    get_applications_error_name_from_enum: unsafe extern "C" fn(u32) -> *const c_char,
+    _property_functions: [*const c_void; 3],
+    set_application_auto_launch: unsafe extern "C" fn(*const c_char, bool) -> u32,
+    get_application_auto_launch: unsafe extern "C" fn(*const c_char) -> bool,
+}
+
+#[derive(Debug)]
+pub struct ApplicationError {
+    pub code: u32,
+    pub name: String,
+}
+
+impl std::fmt::Display for ApplicationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} ({})", self.name, self.code)
+    }
+}
+
+impl std::error::Error for ApplicationError {}
+
+/// SteamVR's list of installed applications.
+pub struct Applications {
+    table: &'static FnTable,
+}
+
+impl Applications {
+    /// The interface of the current OpenVR session, which has to be
+    /// initialized already.
+    pub fn get() -> Option<Applications> {
+        let mut error = openvr_sys2::EVRInitError::VRInitError_None;
+        let table = unsafe { VR_GetGenericInterface(FN_TABLE_VERSION.as_ptr() as *const c_char, &mut error) };
+
+        if table.is_null() {
+            return None;
+        }
+
+        Some(Applications { table: unsafe { &*(table as *const FnTable) } })
+    }
+
+    fn check(&self, code: u32) -> Result<(), ApplicationError> {
+        if code == APPLICATION_ERROR_NONE {
+            return Ok(());
+        }
+
+        let name = unsafe {
+            let name = (self.table.get_applications_error_name_from_enum)(code);
+            if name.is_null() {
+                "Unknown error".to_string()
+            } else {
+                CStr::from_ptr(name).to_string_lossy().into_owned()
+            }
+        };
+
+        Err(ApplicationError { code, name })
+    }
+
+    /// Registers the applications described in the manifest at `path`, which
+    /// has to be absolute, until they are removed again.
+    pub fn add_manifest(&self, path: &Path) -> Result<(), ApplicationError> {
+        let path = CString::new(path.as_os_str().as_bytes()).unwrap_or_default();
+        self.check(unsafe { (self.table.add_application_manifest)(path.as_ptr(), false) })
+    }
+
+    pub fn remove_manifest(&self, path: &Path) -> Result<(), ApplicationError> {
+        let path = CString::new(path.as_os_str().as_bytes()).unwrap_or_default();
+        self.check(unsafe { (self.table.remove_application_manifest)(path.as_ptr()) })
+    }
+
+    pub fn is_installed(&self, app_key: &str) -> bool {
+        let app_key = CString::new(app_key).unwrap_or_default();
+        unsafe { (self.table.is_application_installed)(app_key.as_ptr()) }
+    }
+
+    /// Makes SteamVR start the application whenever it starts.
+    pub fn set_auto_launch(&self, app_key: &str, enabled: bool) -> Result<(), ApplicationError> {
+        let app_key = CString::new(app_key).unwrap_or_default();
+        self.check(unsafe { (self.table.set_application_auto_launch)(app_key.as_ptr(), enabled) })
+    }
+
+    pub fn auto_launch(&self, app_key: &str) -> bool {
+        let app_key = CString::new(app_key).unwrap_or_default();
+        unsafe { (self.table.get_application_auto_launch)(app_key.as_ptr()) }
+    }
+}
@@ -0,0 +1,110 @@
+use std::time::{Duration, Instant};
+
+use crate::config::PlayersConfig;
+
+/// Snapshot of everything the overlay displays about a single player.
+#[derive(Clone, Debug)]
+pub struct PlayerState {
+    pub bus_name: String,
+    pub identity: String,
+    pub metadata: mpris::Metadata,
+    pub volume: Option<f64>,
+    pub position: Option<Duration>,
+    pub position_updated: Instant,
+    pub rate: f64,
+    pub playback_status: mpris::PlaybackStatus,
+    pub shuffle: Option<bool>,
+    pub loop_status: Option<mpris::LoopStatus>,
+    pub can_go_previous: bool,
+    pub can_go_next: bool,
+    pub can_stop: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PlayerCommand {
+    Play,
+    Pause,
+    Stop,
+    Previous,
+    Next,
+    SetVolume(f64),
+    SetPosition(mpris::TrackID, Duration),
+    SetShuffle(bool),
+    SetLoopStatus(mpris::LoopStatus),
+}
+
+/// Source of media players for the overlay, and sink for the commands the
+/// user issues to them.
+pub trait MediaBackend {
+    /// Returns the current list of players if it changed since the last call.
+    fn poll_players(&mut self) -> Option<Vec<PlayerState>>;
+
+    fn send(&mut self, bus_name: &str, command: PlayerCommand);
+
+    /// Applies new polling settings, for backends that have any.
+    fn reconfigure(&mut self, _config: &PlayersConfig) {}
+
+    /// Why no players can be reached at all, such as the session bus being
+    /// unavailable.
+    fn connection_error(&self) -> Option<String> {
+        None
+    }
+}
+
+impl PlayerState {
+    /// Position extrapolated to the current time, since players do not signal
+    /// regular progress through the track.
+    pub fn current_position(&self) -> Option<Duration> {
+        let position = self.position?;
+
+        if self.playback_status == mpris::PlaybackStatus::Playing {
+            Some(position + self.position_updated.elapsed().mul_f64(self.rate.max(0.0)))
+        } else {
+            Some(position)
+        }
+    }
+
+    /// Updates the state the way a well-behaved player would after receiving
+    /// the command.
+    pub fn apply(&mut self, command: &PlayerCommand) {
+        match command {
+            PlayerCommand::Play => {
+                self.position = self.current_position();
+                self.position_updated = Instant::now();
+                self.playback_status = mpris::PlaybackStatus::Playing;
+            },
+            PlayerCommand::Pause => {
+                self.position = self.current_position();
+                self.position_updated = Instant::now();
+                self.playback_status = mpris::PlaybackStatus::Paused;
+            },
+            PlayerCommand::Stop => {
+                self.position = self.position.map(|_| Duration::ZERO);
+                self.position_updated = Instant::now();
+                self.playback_status = mpris::PlaybackStatus::Stopped;
+            },
+            PlayerCommand::Previous | PlayerCommand::Next => (),
+            PlayerCommand::SetVolume(volume) => {
+                if self.volume.is_some() {
+                    self.volume = Some(*volume);
+                }
+            },
+            PlayerCommand::SetPosition(_, position) => {
+                if self.position.is_some() {
+                    self.position = Some(*position);
+                    self.position_updated = Instant::now();
+                }
+            },
+            PlayerCommand::SetShuffle(state) => {
+                if self.shuffle.is_some() {
+                    self.shuffle = Some(*state);
+                }
+            },
+            PlayerCommand::SetLoopStatus(status) => {
+                if self.loop_status.is_some() {
+                    self.loop_status = Some(*status);
+                }
+            },
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use clap::{Args, Parser, Subcommand};
+
+use std::path::PathBuf;
+
+/// Control and view the state of media players from the SteamVR dashboard.
+///
+/// Without a command, runs the overlay.
+#[derive(Parser)]
+#[command(version, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    /// Configuration file to use instead of
+    /// $XDG_CONFIG_HOME/mpris-openvr-overlay/config.toml
+    #[arg(long, global = true, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Most detailed messages to print: off, error, warn, info, debug or
+    /// trace. RUST_LOG can set it per module.
+    #[arg(long, global = true, value_name = "LEVEL", default_value = "warn")]
+    pub log_level: log::LevelFilter,
+
+    #[command(flatten)]
+    pub run: RunArgs,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Args)]
+pub struct RunArgs {
+    /// Show the panel in a desktop window instead of SteamVR
+    #[arg(long)]
+    pub desktop: bool,
+
+    /// Wait for SteamVR to start, and again whenever it quits, instead of
+    /// starting it or exiting with it
+    #[arg(long, conflicts_with = "desktop")]
+    pub daemon: bool,
+
+    /// Own the bus name D-Bus starts the service by, as installed with
+    /// `install --systemd --dbus-activation`
+    #[arg(long, requires = "daemon")]
+    pub dbus_activation: bool,
+}
+
+#[derive(Args)]
+pub struct InstallArgs {
+    /// Also make SteamVR start the overlay along with itself
+    #[arg(long, conflicts_with = "systemd")]
+    pub autostart: bool,
+
+    /// Install a systemd user service that runs the overlay with the desktop
+    /// session and waits for SteamVR, instead of registering it with SteamVR
+    #[arg(long)]
+    pub systemd: bool,
+
+    /// Also let D-Bus start the service
+    #[arg(long, requires = "systemd")]
+    pub dbus_activation: bool,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the overlay (the default)
+    Run(RunArgs),
+    /// Register this binary as an overlay with the running SteamVR, or as a
+    /// user service, started with --config if given
+    Install(InstallArgs),
+    /// Remove the overlay from SteamVR's applications
+    Uninstall,
+    /// Print the SteamVR manifest that install writes, for packaging
+    Manifest {
+        /// Installed overlay binary, instead of this one
+        #[arg(long, value_name = "FILE")]
+        binary: Option<PathBuf>,
+        /// Installed icon to show in SteamVR
+        #[arg(long, value_name = "FILE")]
+        image: Option<PathBuf>,
+    },
+    /// Print the media players on the session bus, one per line
+    ListPlayers,
+    /// Print the state of the configuration, SteamVR and media players
+    Status,
+    /// Draw the panel with demo players into a PNG file
+    RenderPng {
+        #[arg(default_value = "screenshot.png")]
+        path: PathBuf,
+    },
+}
@@ -0,0 +1,423 @@
+use inotify::{Inotify, WatchMask};
+
+use serde::{Deserialize, Serialize};
+
+use std::path::{Path, PathBuf};
+
+/// Directory name used under the XDG base directories.
+pub const APP_NAME: &str = "mpris-openvr-overlay";
+
+/// Longest overlay key and name OpenVR accepts (`k_unVROverlayMaxKeyLength`
+/// and `k_unVROverlayMaxNameLength`), including the terminating NUL.
+const MAX_KEY_LENGTH: usize = 256;
+const MAX_NAME_LENGTH: usize = 128;
+
+/// Largest texture size every OpenGL 3.2 implementation supports.
+const MAX_TEXTURE_SIZE: usize = 8192;
+
+/// Everything that can be set in `config.toml`. Missing keys keep their
+/// default value.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub overlay: OverlayConfig,
+    pub players: PlayersConfig,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OverlayConfig {
+    /// Identifies the overlay to SteamVR; changing it makes it a different
+    /// overlay as far as SteamVR is concerned.
+    pub key: String,
+    /// Shown on the dashboard tab.
+    pub name: String,
+    /// Size of the overlay texture, in pixels.
+    pub width: usize,
+    pub height: usize,
+    /// Size of the overlay in the mouse coordinates SteamVR reports, which
+    /// defaults to the texture size.
+    pub mouse_scale: Option<[f64; 2]>,
+    /// Width of the overlay in the dashboard.
+    pub width_in_meters: f64,
+    /// Pixels per egui point.
+    pub ui_scale: f64,
+    pub theme: Theme,
+    /// Longest wait for SteamVR events while the overlay is hidden, before
+    /// the player list and configuration are checked again. Events still end
+    /// the wait within a few tens of milliseconds, so a longer interval only
+    /// delays the rest.
+    pub hidden_poll_interval_ms: u32,
+    /// Whether SteamVR starts the overlay along with itself. Left as SteamVR
+    /// has it when unset.
+    pub autostart: Option<bool>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    #[default]
+    Dark,
+    Light,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlayersConfig {
+    /// How long to wait for a player to answer a D-Bus call.
+    pub dbus_timeout_ms: i32,
+    /// Identities of players not to show, such as "VLC media player".
+    pub hidden: Vec<String>,
+}
+
+impl Default for OverlayConfig {
+    fn default() -> OverlayConfig {
+        OverlayConfig {
+            key: APP_NAME.to_string(),
+            name: "Media Player".to_string(),
+            width: 2048,
+            height: 768,
+            mouse_scale: None,
+            width_in_meters: 2.0,
+            ui_scale: 4.2,
+            theme: Theme::Dark,
+            hidden_poll_interval_ms: 250,
+            autostart: None,
+        }
+    }
+}
+
+impl Default for PlayersConfig {
+    fn default() -> PlayersConfig {
+        PlayersConfig {
+            dbus_timeout_ms: 500,
+            hidden: vec![],
+        }
+    }
+}
+
+impl OverlayConfig {
+    pub fn mouse_scale(&self) -> [f32; 2] {
+        self.mouse_scale.map_or([self.width as f32, self.height as f32], |scale| scale.map(|s| s as f32))
+    }
+}
+
+impl PlayersConfig {
+    /// Whether the player worker has to be reconfigured to go from `self` to
+    /// `other`. Hidden players are only filtered out by the UI.
+    pub fn worker_differs(&self, other: &PlayersConfig) -> bool {
+        self.dbus_timeout_ms != other.dbus_timeout_ms
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid(PathBuf, String),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ConfigError::Read(path, err) => write!(f, "Failed to read {}: {}", path.display(), err),
+            ConfigError::Parse(path, err) => write!(f, "Failed to parse {}: {}", path.display(), err),
+            ConfigError::Invalid(path, message) => write!(f, "Invalid configuration in {}: {}", path.display(), message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// `$<var>` if it is set to an absolute path, as the XDG base directory
+/// specification asks, or `$HOME/<fallback>` otherwise.
+pub fn xdg_dir(var: &str, fallback: &str) -> Option<PathBuf> {
+    std::env::var_os(var).map(PathBuf::from).filter(|path| path.is_absolute()).or_else(|| {
+        std::env::var_os("HOME").map(|home| Path::new(&home).join(fallback))
+    })
+}
+
+/// `$XDG_CONFIG_HOME/mpris-openvr-overlay/config.toml`.
+pub fn default_path() -> Option<PathBuf> {
+    xdg_dir("XDG_CONFIG_HOME", ".config").map(|dir| dir.join(APP_NAME).join("config.toml"))
+}
+
+fn check(condition: bool, message: impl FnOnce() -> String) -> Result<(), String> {
+    if condition { Ok(()) } else { Err(message()) }
+}
+
+impl Config {
+    /// Reads the configuration at `path`, or the defaults if there is no file.
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(err) => return Err(ConfigError::Read(path.to_path_buf(), err)),
+        };
+
+        let config = Config::parse(&text).map_err(|err| ConfigError::Parse(path.to_path_buf(), err))?;
+        config.validate().map_err(|message| ConfigError::Invalid(path.to_path_buf(), message))?;
+
+        Ok(config)
+    }
+
+    pub fn parse(text: &str) -> Result<Config, toml::de::Error> {
+        toml::from_str(text)
+    }
+
+    /// Replaces the file at `path` in one step, so that neither the overlay
+    /// watching it nor a crash halfway through sees a partial file. Comments
+    /// in the old file are lost.
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        use std::io::Write;
+
+        let text = toml::to_string_pretty(self).
+            map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+
+        let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        std::fs::create_dir_all(dir)?;
+
+        let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+        temp_name.push(format!(".{}.tmp", std::process::id()));
+        let temp_path = dir.join(temp_name);
+
+        let result = std::fs::File::create(&temp_path).and_then(|mut file| {
+            file.write_all(text.as_bytes())?;
+            file.sync_all()
+        }).and_then(|_| std::fs::rename(&temp_path, path));
+
+        if result.is_err() {
+            let _ = std::fs::remove_file(&temp_path);
+        }
+
+        result
+    }
+
+    /// Checks the values the TOML types alone do not restrict.
+    pub fn validate(&self) -> Result<(), String> {
+        let overlay = &self.overlay;
+
+        check(!overlay.key.is_empty() && overlay.key.len() < MAX_KEY_LENGTH, || {
+            format!("overlay.key must be between 1 and {} bytes long", MAX_KEY_LENGTH - 1)
+        })?;
+        check(!overlay.key.contains(char::is_whitespace), || {
+            "overlay.key must not contain whitespace".to_string()
+        })?;
+        check(!overlay.name.is_empty() && overlay.name.len() < MAX_NAME_LENGTH, || {
+            format!("overlay.name must be between 1 and {} bytes long", MAX_NAME_LENGTH - 1)
+        })?;
+
+        for (name, value) in [("overlay.width", overlay.width), ("overlay.height", overlay.height)] {
+            check((1..=MAX_TEXTURE_SIZE).contains(&value), || {
+                format!("{} must be between 1 and {} pixels, not {}", name, MAX_TEXTURE_SIZE, value)
+            })?;
+        }
+
+        if let Some(scale) = overlay.mouse_scale {
+            check(scale.iter().all(|&s| s.is_finite() && s > 0.0), || {
+                format!("overlay.mouse_scale must be two positive numbers, not {:?}", scale)
+            })?;
+        }
+
+        check(overlay.width_in_meters.is_finite() && overlay.width_in_meters > 0.0, || {
+            format!("overlay.width_in_meters must be positive, not {}", overlay.width_in_meters)
+        })?;
+        check(overlay.ui_scale.is_finite() && overlay.ui_scale > 0.0, || {
+            format!("overlay.ui_scale must be positive, not {}", overlay.ui_scale)
+        })?;
+        check(overlay.hidden_poll_interval_ms > 0, || {
+            "overlay.hidden_poll_interval_ms must be positive".to_string()
+        })?;
+
+        check(self.players.dbus_timeout_ms > 0, || {
+            "players.dbus_timeout_ms must be positive".to_string()
+        })?;
+
+        Ok(())
+    }
+}
+
+/// Notices when the configuration file is written, replaced or removed.
+///
+/// The directory is watched rather than the file, since editors usually save
+/// by writing a new file and renaming it over the old one.
+pub struct ConfigWatcher {
+    inotify: Inotify,
+    path: PathBuf,
+    buffer: Vec<u8>,
+}
+
+impl ConfigWatcher {
+    /// Creates the directory of `path` if needed, so that a configuration
+    /// file created later is picked up too.
+    pub fn new(path: &Path) -> std::io::Result<ConfigWatcher> {
+        let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        std::fs::create_dir_all(dir)?;
+
+        /* Not CREATE, which comes before anything is written to a new file */
+        let inotify = Inotify::init()?;
+        inotify.watches().add(dir, WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO | WatchMask::DELETE)?;
+
+        Ok(ConfigWatcher { inotify, path: path.to_path_buf(), buffer: vec![0; 4096] })
+    }
+
+    /// Whether the file changed since the last call. Never blocks.
+    fn changed(&mut self) -> bool {
+        let file_name = self.path.file_name();
+        let mut changed = false;
+
+        while let Ok(events) = self.inotify.read_events(&mut self.buffer) {
+            let mut any = false;
+            for event in events {
+                any = true;
+                changed |= event.name.is_some() && event.name == file_name;
+            }
+
+            if !any {
+                break;
+            }
+        }
+
+        changed
+    }
+
+    /// The configuration as it is now, if the file changed since the last
+    /// call.
+    pub fn reload(&mut self) -> Option<Result<Config, ConfigError>> {
+        self.changed().then(|| Config::load(&self.path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invalid(text: &str) -> String {
+        Config::parse(text).expect("Failed to parse").validate().expect_err("Accepted an invalid value")
+    }
+
+    #[test]
+    fn empty_file_gives_the_defaults() {
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn partial_tables_keep_their_defaults() {
+        let config = Config::parse("[overlay]\nwidth = 1024\n\n[players]\nhidden = [\"VLC media player\"]\n").unwrap();
+
+        assert_eq!(config.overlay, OverlayConfig { width: 1024, ..OverlayConfig::default() });
+        assert_eq!(config.players, PlayersConfig {
+            hidden: vec!["VLC media player".to_string()],
+            ..PlayersConfig::default()
+        });
+        assert_eq!(config.validate(), Ok(()));
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        for text in ["colour = \"red\"", "[overlay]\nwidht = 1024", "[players]\nhiden = []"] {
+            let err = Config::parse(text).expect_err(text).to_string();
+            assert!(err.contains("unknown field"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn out_of_range_values_are_rejected() {
+        let long_key = format!("[overlay]\nkey = \"{}\"", "k".repeat(MAX_KEY_LENGTH));
+        let long_name = format!("[overlay]\nname = \"{}\"", "n".repeat(MAX_NAME_LENGTH));
+
+        let cases = [
+            ("[overlay]\nkey = \"\"", "overlay.key must be between"),
+            (long_key.as_str(), "overlay.key must be between"),
+            ("[overlay]\nkey = \"my overlay\"", "overlay.key must not contain whitespace"),
+            ("[overlay]\nname = \"\"", "overlay.name must be between"),
+            (long_name.as_str(), "overlay.name must be between"),
+            ("[overlay]\nwidth = 0", "overlay.width must be between 1 and 8192 pixels, not 0"),
+            ("[overlay]\nheight = 8193", "overlay.height must be between 1 and 8192 pixels, not 8193"),
+            ("[overlay]\nmouse_scale = [100.0, 0.0]", "overlay.mouse_scale must be two positive numbers"),
+            ("[overlay]\nmouse_scale = [nan, 100.0]", "overlay.mouse_scale must be two positive numbers"),
+            ("[overlay]\nwidth_in_meters = -1.0", "overlay.width_in_meters must be positive, not -1"),
+            ("[overlay]\nwidth_in_meters = inf", "overlay.width_in_meters must be positive, not inf"),
+            ("[overlay]\nui_scale = 0.0", "overlay.ui_scale must be positive, not 0"),
+            ("[overlay]\nhidden_poll_interval_ms = 0", "overlay.hidden_poll_interval_ms must be positive"),
+            ("[players]\ndbus_timeout_ms = -5", "players.dbus_timeout_ms must be positive"),
+        ];
+
+        for (text, message) in cases {
+            let err = invalid(text);
+            assert!(err.starts_with(message), "{:?} gave {:?}", text, err);
+        }
+    }
+
+    #[test]
+    fn wrong_types_are_parse_errors() {
+        assert!(Config::parse("[overlay]\nwidth = -1").is_err());
+        assert!(Config::parse("[overlay]\ntheme = \"blue\"").is_err());
+    }
+
+    #[test]
+    fn missing_file_gives_the_defaults() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(Config::load(&dir.path().join("config.toml")).unwrap(), Config::default());
+    }
+
+    #[test]
+    fn invalid_file_names_the_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "[overlay]\nui_scale = 0.0\n").unwrap();
+
+        let err = Config::load(&path).unwrap_err();
+        assert!(matches!(err, ConfigError::Invalid(..)), "{:?}", err);
+        assert!(err.to_string().contains(&path.display().to_string()), "{}", err);
+    }
+
+    #[test]
+    fn save_then_load_round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("dir").join("config.toml");
+
+        let mut config = Config::default();
+        config.overlay.theme = Theme::Light;
+        config.overlay.mouse_scale = Some([1000.0, 375.0]);
+        config.overlay.autostart = Some(true);
+        config.players.hidden = vec!["VLC media player".to_string()];
+
+        config.save(&path).unwrap();
+        assert_eq!(Config::load(&path).unwrap(), config);
+
+        /* Saving again replaces the file */
+        config.overlay.theme = Theme::Dark;
+        config.save(&path).unwrap();
+        assert_eq!(Config::load(&path).unwrap(), config);
+
+        let files: Vec<_> = std::fs::read_dir(path.parent().unwrap()).unwrap().
+            map(|entry| entry.unwrap().file_name()).collect();
+        assert_eq!(files, ["config.toml"]);
+    }
+
+    #[test]
+    fn watcher_reloads_once_per_change_to_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        let mut watcher = ConfigWatcher::new(&path).unwrap();
+
+        assert!(watcher.reload().is_none());
+
+        std::fs::write(dir.path().join("other.toml"), "theme = 1").unwrap();
+        assert!(watcher.reload().is_none());
+
+        /* The way editors save: a new file renamed over the old one */
+        let temp_path = dir.path().join("config.toml.swp");
+        std::fs::write(&temp_path, "[overlay]\ntheme = \"light\"\n").unwrap();
+        std::fs::rename(&temp_path, &path).unwrap();
+
+        let config = watcher.reload().expect("Rename not noticed").unwrap();
+        assert_eq!(config.overlay.theme, Theme::Light);
+        assert!(watcher.reload().is_none());
+
+        std::fs::write(&path, "[overlay]\ntheme = \"dark\"\n").unwrap();
+        assert_eq!(watcher.reload().expect("Write not noticed").unwrap(), Config::default());
+        assert!(watcher.reload().is_none());
+    }
+}
@@ -0,0 +1,144 @@
+use egui_sdl2_gl::egui;
+
+/// Width of the cursor overlays in the dashboard.
+pub const CURSOR_WIDTH_METERS: f32 = 0.06;
+
+/// Size of the bundled cursor images, in pixels.
+const CURSOR_IMAGE_SIZE: f32 = 64.0;
+
+/// Cursors the overlay shows instead of SteamVR's laser dot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Cursor {
+    PointingHand,
+    Text,
+    ResizeHorizontal,
+    ResizeVertical,
+    ResizeNeSw,
+    ResizeNwSe,
+    Move,
+    NotAllowed,
+}
+
+impl Cursor {
+    /// The cursor for an egui cursor icon, or `None` for SteamVR's own.
+    pub fn from_egui(icon: egui::CursorIcon) -> Option<Cursor> {
+        use egui::CursorIcon as Icon;
+
+        match icon {
+            Icon::PointingHand => Some(Cursor::PointingHand),
+            Icon::Text | Icon::VerticalText => Some(Cursor::Text),
+            Icon::ResizeHorizontal | Icon::ResizeEast | Icon::ResizeWest | Icon::ResizeColumn => Some(Cursor::ResizeHorizontal),
+            Icon::ResizeVertical | Icon::ResizeNorth | Icon::ResizeSouth | Icon::ResizeRow => Some(Cursor::ResizeVertical),
+            Icon::ResizeNeSw | Icon::ResizeNorthEast | Icon::ResizeSouthWest => Some(Cursor::ResizeNeSw),
+            Icon::ResizeNwSe | Icon::ResizeNorthWest | Icon::ResizeSouthEast => Some(Cursor::ResizeNwSe),
+            Icon::Move | Icon::AllScroll | Icon::Grab | Icon::Grabbing => Some(Cursor::Move),
+            Icon::NotAllowed | Icon::NoDrop => Some(Cursor::NotAllowed),
+            _ => None,
+        }
+    }
+
+    /// Name used for the overlay key of the cursor.
+    pub fn name(self) -> &'static str {
+        match self {
+            Cursor::PointingHand => "pointing_hand",
+            Cursor::Text => "text",
+            Cursor::ResizeHorizontal => "resize_horizontal",
+            Cursor::ResizeVertical => "resize_vertical",
+            Cursor::ResizeNeSw => "resize_nesw",
+            Cursor::ResizeNwSe => "resize_nwse",
+            Cursor::Move => "move",
+            Cursor::NotAllowed => "not_allowed",
+        }
+    }
+
+    /// PNG image of the cursor.
+    pub fn image(self) -> &'static [u8] {
+        match self {
+            Cursor::PointingHand => include_bytes!("../assets/cursors/pointing_hand.png"),
+            Cursor::Text => include_bytes!("../assets/cursors/text.png"),
+            Cursor::ResizeHorizontal => include_bytes!("../assets/cursors/resize_horizontal.png"),
+            Cursor::ResizeVertical => include_bytes!("../assets/cursors/resize_vertical.png"),
+            Cursor::ResizeNeSw => include_bytes!("../assets/cursors/resize_nesw.png"),
+            Cursor::ResizeNwSe => include_bytes!("../assets/cursors/resize_nwse.png"),
+            Cursor::Move => include_bytes!("../assets/cursors/move.png"),
+            Cursor::NotAllowed => include_bytes!("../assets/cursors/not_allowed.png"),
+        }
+    }
+
+    /// Point of the image that should be on the laser, in image pixels from
+    /// the top left.
+    fn hotspot(self) -> [f32; 2] {
+        match self {
+            Cursor::PointingHand => [26.0, 5.0],
+            _ => [CURSOR_IMAGE_SIZE / 2.0, CURSOR_IMAGE_SIZE / 2.0],
+        }
+    }
+
+    /// Where SteamVR should draw the cursor, which it centers on that
+    /// position, for its hotspot to be on the laser at `laser`. Both are in
+    /// overlay mouse coordinates, with the origin at the bottom left, of which
+    /// there are `mouse_units_per_meter` across the overlay.
+    pub fn position_for(self, laser: [f32; 2], mouse_units_per_meter: f32) -> [f32; 2] {
+        let units_per_image_pixel =
+            (CURSOR_WIDTH_METERS / CURSOR_IMAGE_SIZE) * mouse_units_per_meter;
+        let [x, y] = self.hotspot();
+
+        [
+            laser[0] + (CURSOR_IMAGE_SIZE / 2.0 - x) * units_per_image_pixel,
+            laser[1] - (CURSOR_IMAGE_SIZE / 2.0 - y) * units_per_image_pixel,
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 2 m wide overlay with the default mouse scale of 2048 across.
+    const UNITS_PER_METER: f32 = 1024.0;
+
+    fn assert_near(actual: [f32; 2], expected: [f32; 2]) {
+        assert!(actual.iter().zip(expected).all(|(a, e)| (a - e).abs() < 1e-3),
+                "{:?} is not {:?}", actual, expected);
+    }
+
+    #[test]
+    fn egui_icons_map_to_cursors() {
+        assert_eq!(Cursor::from_egui(egui::CursorIcon::Default), None);
+        assert_eq!(Cursor::from_egui(egui::CursorIcon::PointingHand), Some(Cursor::PointingHand));
+        assert_eq!(Cursor::from_egui(egui::CursorIcon::VerticalText), Some(Cursor::Text));
+        assert_eq!(Cursor::from_egui(egui::CursorIcon::ResizeColumn), Some(Cursor::ResizeHorizontal));
+        assert_eq!(Cursor::from_egui(egui::CursorIcon::ResizeSouth), Some(Cursor::ResizeVertical));
+        assert_eq!(Cursor::from_egui(egui::CursorIcon::ResizeSouthWest), Some(Cursor::ResizeNeSw));
+        assert_eq!(Cursor::from_egui(egui::CursorIcon::ResizeNorthWest), Some(Cursor::ResizeNwSe));
+        assert_eq!(Cursor::from_egui(egui::CursorIcon::Grabbing), Some(Cursor::Move));
+        assert_eq!(Cursor::from_egui(egui::CursorIcon::NoDrop), Some(Cursor::NotAllowed));
+    }
+
+    #[test]
+    fn centred_cursors_sit_on_the_laser() {
+        assert_near(Cursor::Text.position_for([100.0, 200.0], UNITS_PER_METER), [100.0, 200.0]);
+        assert_near(Cursor::Move.position_for([0.0, 0.0], UNITS_PER_METER), [0.0, 0.0]);
+    }
+
+    #[test]
+    fn pointing_hand_is_moved_so_its_fingertip_is_on_the_laser() {
+        /* 0.06 m across 64 pixels, at 1024 units per meter */
+        let units_per_pixel = 0.96;
+
+        /* The fingertip is left of the image's centre and above it, and y
+         * grows upwards in mouse coordinates */
+        assert_near(Cursor::PointingHand.position_for([100.0, 200.0], UNITS_PER_METER),
+                    [100.0 + 6.0 * units_per_pixel, 200.0 - 27.0 * units_per_pixel]);
+    }
+
+    #[test]
+    fn offset_scales_with_mouse_units() {
+        let laser = [500.0, 500.0];
+        let near = Cursor::PointingHand.position_for(laser, UNITS_PER_METER);
+        let far = Cursor::PointingHand.position_for(laser, 2.0 * UNITS_PER_METER);
+
+        assert_near([far[0] - laser[0], far[1] - laser[1]],
+                    [2.0 * (near[0] - laser[0]), 2.0 * (near[1] - laser[1])]);
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use mpris::MetadataValue;
+
+use crate::backend::{MediaBackend, PlayerCommand, PlayerState};
+
+/// In-memory backend whose players are scripted by the caller. Commands are
+/// recorded and applied to the matching player like a real one would.
+#[derive(Default)]
+pub struct FakeBackend {
+    players: Vec<PlayerState>,
+    commands: Vec<(String, PlayerCommand)>,
+    changed: bool,
+}
+
+impl FakeBackend {
+    pub fn new(players: Vec<PlayerState>) -> FakeBackend {
+        FakeBackend { players, commands: vec![], changed: true }
+    }
+
+    #[cfg(test)]
+    pub fn players(&self) -> &[PlayerState] {
+        &self.players
+    }
+
+    #[cfg(test)]
+    pub fn set_players(&mut self, players: Vec<PlayerState>) {
+        self.players = players;
+        self.changed = true;
+    }
+
+    pub fn update_player(&mut self, bus_name: &str, f: impl FnOnce(&mut PlayerState)) {
+        if let Some(player) = self.players.iter_mut().find(|p| p.bus_name == bus_name) {
+            f(player);
+            self.changed = true;
+        }
+    }
+
+    /// Returns every command received since the last call.
+    #[cfg(test)]
+    pub fn take_commands(&mut self) -> Vec<(String, PlayerCommand)> {
+        std::mem::take(&mut self.commands)
+    }
+}
+
+impl MediaBackend for FakeBackend {
+    fn poll_players(&mut self) -> Option<Vec<PlayerState>> {
+        if self.changed {
+            self.changed = false;
+            Some(self.players.clone())
+        } else {
+            None
+        }
+    }
+
+    fn send(&mut self, bus_name: &str, command: PlayerCommand) {
+        self.update_player(bus_name, |player| player.apply(&command));
+        self.commands.push((bus_name.to_string(), command));
+    }
+}
+
+/// A paused player with a track loaded and every capability enabled.
+pub fn fake_player(name: &str, identity: &str) -> PlayerState {
+    let track_id = format!("/org/mpris/MediaPlayer2/Track/{}", name);
+
+    PlayerState {
+        bus_name: format!("org.mpris.MediaPlayer2.{}", name),
+        identity: identity.to_string(),
+        metadata: fake_metadata(&track_id, "Track", &["Artist"], Some(Duration::from_secs(200)), None),
+        volume: Some(0.5),
+        position: Some(Duration::from_secs(42)),
+        position_updated: Instant::now(),
+        rate: 1.0,
+        playback_status: mpris::PlaybackStatus::Paused,
+        shuffle: Some(false),
+        loop_status: Some(mpris::LoopStatus::None),
+        can_go_previous: true,
+        can_go_next: true,
+        can_stop: true,
+    }
+}
+
+pub fn fake_metadata(track_id: &str, title: &str, artists: &[&str],
+                     length: Option<Duration>, art_url: Option<&str>) -> mpris::Metadata {
+    let mut values = HashMap::new();
+
+    values.insert("mpris:trackid".to_string(), MetadataValue::String(track_id.to_string()));
+    values.insert("xesam:title".to_string(), MetadataValue::String(title.to_string()));
+    values.insert("xesam:artist".to_string(), MetadataValue::Array(
+        artists.iter().map(|&a| MetadataValue::String(a.to_string())).collect()));
+
+    if let Some(length) = length {
+        values.insert("mpris:length".to_string(), MetadataValue::I64(length.as_micros() as i64));
+    }
+
+    if let Some(url) = art_url {
+        values.insert("mpris:artUrl".to_string(), MetadataValue::String(url.to_string()));
+    }
+
+    mpris::Metadata::from(values)
+}
+
+/// The players shown in `screenshot.png`.
+pub fn demo_players() -> Vec<PlayerState> {
+    let mut lollypop = fake_player("Lollypop", "Lollypop");
+    lollypop.metadata = fake_metadata(
+        "/org/gnome/Lollypop/Track/1", "Les Voyages En Train", &["Grand Corps Malade"],
+        Some(Duration::from_secs(177)), None);
+    lollypop.volume = Some(0.75);
+    lollypop.position = Some(Duration::from_secs(110));
+    lollypop.shuffle = Some(true);
+
+    vec![lollypop, fake_player("vlc", "VLC media player")]
+}
@@ -0,0 +1,497 @@
+use egui_sdl2_gl::egui;
+
+use openvr_sys2::EVREventType::*;
+
+/// `EVRMouseButton` values, which openvr_sys2 does not generate.
+pub const MOUSE_BUTTON_LEFT: u32 = 1;
+pub const MOUSE_BUTTON_RIGHT: u32 = 2;
+pub const MOUSE_BUTTON_MIDDLE: u32 = 4;
+
+/// The parts of a `VREvent_t` the overlay cares about, read out of the
+/// event's data union.
+#[derive(Clone, Debug, PartialEq)]
+pub enum VrEvent {
+    Quit,
+    OverlayClosed,
+    OverlayShown,
+    OverlayHidden,
+    /// The dashboard opened, possibly on another overlay.
+    DashboardActivated,
+    FocusEnter,
+    FocusLeave,
+    /// Coordinates in overlay mouse coordinates, with the origin at the
+    /// bottom left.
+    MouseMove { x: f32, y: f32 },
+    MouseButton { x: f32, y: f32, button: u32, pressed: bool },
+    Scroll { xdelta: f32, ydelta: f32 },
+    /// Complete characters typed on the SteamVR keyboard.
+    KeyboardInput(String),
+    KeyboardDone,
+    KeyboardClosed,
+    Other,
+}
+
+/// Reassembles UTF-8 sequences that the SteamVR keyboard splits across
+/// `VREvent_KeyboardCharInput` events.
+#[derive(Debug, Default)]
+pub struct Utf8Assembler {
+    pending: Vec<u8>,
+}
+
+impl Utf8Assembler {
+    /// Drops an unfinished sequence, which would otherwise be prepended to the
+    /// next input.
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Returns the complete characters available after appending `bytes`,
+    /// keeping an unfinished sequence at the end for the next call. Bytes that
+    /// can never form a character become U+FFFD.
+    pub fn push(&mut self, bytes: &[u8]) -> String {
+        self.pending.extend_from_slice(bytes);
+
+        let mut text = String::new();
+
+        loop {
+            match std::str::from_utf8(&self.pending) {
+                Ok(complete) => {
+                    text.push_str(complete);
+                    self.pending.clear();
+                    break;
+                },
+                Err(error) => {
+                    let (valid, rest) = self.pending.split_at(error.valid_up_to());
+                    text.push_str(std::str::from_utf8(valid).unwrap());
+
+                    match error.error_len() {
+                        Some(len) => {
+                            text.push(char::REPLACEMENT_CHARACTER);
+                            self.pending = rest[len..].to_vec();
+                        },
+                        None => {
+                            self.pending = rest.to_vec();
+                            break;
+                        },
+                    }
+                },
+            }
+        }
+
+        text
+    }
+}
+
+impl VrEvent {
+    /// `keyboard` carries partial characters over from previous events, until
+    /// the user is done with the keyboard.
+    pub fn decode(event: &openvr_sys2::VREvent_t, keyboard: &mut Utf8Assembler) -> VrEvent {
+        let event_type = event.eventType.try_into().unwrap_or(VREvent_None);
+        match event_type {
+            VREvent_Quit => VrEvent::Quit,
+            VREvent_OverlayClosed => VrEvent::OverlayClosed,
+            VREvent_OverlayShown => VrEvent::OverlayShown,
+            VREvent_OverlayHidden => VrEvent::OverlayHidden,
+            VREvent_DashboardActivated => VrEvent::DashboardActivated,
+            VREvent_FocusEnter => VrEvent::FocusEnter,
+            VREvent_FocusLeave => VrEvent::FocusLeave,
+            VREvent_MouseMove => {
+                let mouse = unsafe { std::ptr::addr_of!(event.data.mouse).read_unaligned() };
+                VrEvent::MouseMove { x: mouse.x, y: mouse.y }
+            },
+            VREvent_MouseButtonDown | VREvent_MouseButtonUp => {
+                let mouse = unsafe { std::ptr::addr_of!(event.data.mouse).read_unaligned() };
+                VrEvent::MouseButton {
+                    x: mouse.x, y: mouse.y, button: mouse.button,
+                    pressed: event_type == VREvent_MouseButtonDown,
+                }
+            },
+            VREvent_ScrollDiscrete | VREvent_ScrollSmooth => {
+                let scroll = unsafe { std::ptr::addr_of!(event.data.scroll).read_unaligned() };
+                VrEvent::Scroll { xdelta: scroll.xdelta, ydelta: scroll.ydelta }
+            },
+            VREvent_KeyboardCharInput => {
+                let keyboard_event = unsafe { std::ptr::addr_of!(event.data.keyboard).read_unaligned() };
+                let bytes: Vec<u8> = keyboard_event.cNewInput.iter().
+                    map(|&c| c as u8).take_while(|&c| c != 0).collect();
+                VrEvent::KeyboardInput(keyboard.push(&bytes))
+            },
+            VREvent_KeyboardDone => {
+                keyboard.clear();
+                VrEvent::KeyboardDone
+            },
+            VREvent_KeyboardClosed => {
+                keyboard.clear();
+                VrEvent::KeyboardClosed
+            },
+            _ => VrEvent::Other,
+        }
+    }
+}
+
+/// What an event does to the overlay itself rather than to the UI.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OverlayStateChange {
+    Quit,
+    Shown,
+    Hidden,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Translation {
+    /// `Event::Paste` is empty, for the caller to fill in from the clipboard.
+    pub events: Vec<egui::Event>,
+    pub state: Option<OverlayStateChange>,
+    /// The text field being edited should lose focus, as the user is done
+    /// with the keyboard.
+    pub release_focus: bool,
+}
+
+/// Where egui last saw the pointer and which buttons it holds, as far as the
+/// events sent to it go.
+#[derive(Debug, Default)]
+pub struct PointerState {
+    pos: Option<egui::Pos2>,
+    pressed: Vec<egui::PointerButton>,
+}
+
+impl PointerState {
+    pub fn update(&mut self, events: &[egui::Event]) {
+        for event in events {
+            match event {
+                egui::Event::PointerMoved(pos) => { self.pos = Some(*pos); },
+                egui::Event::PointerButton { pos, button, pressed, .. } => {
+                    self.pos = Some(*pos);
+                    self.pressed.retain(|b| b != button);
+                    if *pressed {
+                        self.pressed.push(*button);
+                    }
+                },
+                egui::Event::PointerGone => {
+                    self.pos = None;
+                    self.pressed.clear();
+                },
+                _ => ()
+            }
+        }
+    }
+}
+
+const CTRL: egui::Modifiers = egui::Modifiers {
+    ctrl: true, command: true, ..egui::Modifiers::NONE
+};
+
+/// Keys typed as Ctrl+A through Ctrl+Z, which arrive as 0x01 to 0x1a.
+const LETTER_KEYS: [egui::Key; 26] = {
+    use egui::Key::*;
+    [A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z]
+};
+
+fn key_press(key: egui::Key, modifiers: egui::Modifiers) -> [egui::Event; 2] {
+    [true, false].map(|pressed| egui::Event::Key {
+        key,
+        pressed,
+        repeat: false,
+        modifiers
+    })
+}
+
+/// Splits keyboard input into text and presses of the keys its control
+/// characters stand for. Ctrl+C, Ctrl+V and Ctrl+X become the clipboard events
+/// text fields handle instead, with nothing to paste yet.
+fn keyboard_events(input: &str) -> Vec<egui::Event> {
+    let mut events = vec![];
+    let mut text = String::new();
+
+    for c in input.chars() {
+        let key_events = match c {
+            '\n' | '\r' => key_press(egui::Key::Enter, egui::Modifiers::NONE).to_vec(),
+            '\x08' => key_press(egui::Key::Backspace, egui::Modifiers::NONE).to_vec(),
+            '\t' => key_press(egui::Key::Tab, egui::Modifiers::NONE).to_vec(),
+            '\x7f' => key_press(egui::Key::Delete, egui::Modifiers::NONE).to_vec(),
+            '\x1b' => key_press(egui::Key::Escape, egui::Modifiers::NONE).to_vec(),
+            '\x03' => vec![egui::Event::Copy],
+            '\x16' => vec![egui::Event::Paste(String::new())],
+            '\x18' => vec![egui::Event::Cut],
+            '\x01'..='\x1a' => key_press(LETTER_KEYS[c as usize - 1], CTRL).to_vec(),
+            c if c.is_control() => vec![],
+            c => {
+                text.push(c);
+                continue;
+            },
+        };
+
+        if !text.is_empty() {
+            events.push(egui::Event::Text(std::mem::take(&mut text)));
+        }
+
+        events.extend(key_events);
+    }
+
+    if !text.is_empty() {
+        events.push(egui::Event::Text(text));
+    }
+
+    events
+}
+
+/// How the overlay's mouse coordinates map onto the UI.
+#[derive(Clone, Copy, Debug)]
+pub struct Surface {
+    /// Size of the overlay in mouse coordinates, as set with
+    /// `SetOverlayMouseScale`.
+    pub mouse_scale: [f32; 2],
+    /// Size of the UI, in points.
+    pub size: egui::Vec2,
+    pub pixels_per_point: f32,
+}
+
+/// Turns an event into egui input for a UI drawn on `surface`, given the
+/// state of the pointer before the event.
+pub fn translate(event: &VrEvent, surface: &Surface, pointer: &PointerState) -> Translation {
+    let [mouse_width, mouse_height] = surface.mouse_scale;
+    let pixels_per_point = surface.pixels_per_point;
+    let pos = |x: f32, y: f32| egui::pos2(
+        x / mouse_width * surface.size.x,
+        (mouse_height - y) / mouse_height * surface.size.y);
+
+    let mut translation = Translation::default();
+
+    match event {
+        VrEvent::Quit | VrEvent::OverlayClosed => {
+            translation.state = Some(OverlayStateChange::Quit);
+        },
+        VrEvent::OverlayShown => { translation.state = Some(OverlayStateChange::Shown); },
+        VrEvent::OverlayHidden => { translation.state = Some(OverlayStateChange::Hidden); },
+        VrEvent::FocusEnter => { translation.events.push(egui::Event::WindowFocused(true)); },
+        VrEvent::FocusLeave => {
+            /* The laser is gone, so nothing stays hovered or held down */
+            for &button in pointer.pressed.iter() {
+                translation.events.push(egui::Event::PointerButton {
+                    pos: pointer.pos.unwrap_or_default(),
+                    button,
+                    pressed: false,
+                    modifiers: egui::Modifiers::NONE
+                });
+            }
+
+            translation.events.push(egui::Event::PointerGone);
+            translation.events.push(egui::Event::WindowFocused(false));
+        },
+        VrEvent::MouseMove { x, y } => {
+            translation.events.push(egui::Event::PointerMoved(pos(*x, *y)));
+        },
+        VrEvent::MouseButton { x, y, button, pressed } => {
+            translation.events.push(egui::Event::PointerButton {
+                pos: pos(*x, *y),
+                button: match *button {
+                    MOUSE_BUTTON_LEFT => egui::PointerButton::Primary,
+                    MOUSE_BUTTON_RIGHT => egui::PointerButton::Secondary,
+                    MOUSE_BUTTON_MIDDLE => egui::PointerButton::Middle,
+                    _ => egui::PointerButton::Extra1,
+                },
+                pressed: *pressed,
+                modifiers: egui::Modifiers::NONE
+            });
+        },
+        VrEvent::Scroll { xdelta, ydelta } => {
+            translation.events.push(egui::Event::Scroll(
+                egui::vec2(xdelta / pixels_per_point, ydelta / pixels_per_point)));
+        },
+        VrEvent::KeyboardInput(input) => {
+            translation.events = keyboard_events(input);
+        },
+        VrEvent::KeyboardDone | VrEvent::KeyboardClosed => {
+            translation.release_focus = true;
+        },
+        VrEvent::DashboardActivated | VrEvent::Other => (),
+    }
+
+    translation
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::simulated;
+
+    /// A 400×150 point UI at 2 pixels per point on an overlay whose mouse
+    /// coordinates run to 800×300.
+    fn surface() -> Surface {
+        Surface { mouse_scale: [800.0, 300.0], size: egui::vec2(400.0, 150.0), pixels_per_point: 2.0 }
+    }
+
+    fn translate_events(event: VrEvent) -> Vec<egui::Event> {
+        translate(&event, &surface(), &PointerState::default()).events
+    }
+
+    fn button(pos: egui::Pos2, button: egui::PointerButton, pressed: bool) -> egui::Event {
+        egui::Event::PointerButton { pos, button, pressed, modifiers: egui::Modifiers::NONE }
+    }
+
+    #[test]
+    fn mouse_coordinates_are_scaled_and_flipped() {
+        assert_eq!(translate_events(VrEvent::MouseMove { x: 0.0, y: 0.0 }),
+                   [egui::Event::PointerMoved(egui::pos2(0.0, 150.0))]);
+        assert_eq!(translate_events(VrEvent::MouseMove { x: 800.0, y: 300.0 }),
+                   [egui::Event::PointerMoved(egui::pos2(400.0, 0.0))]);
+        assert_eq!(translate_events(VrEvent::MouseMove { x: 200.0, y: 225.0 }),
+                   [egui::Event::PointerMoved(egui::pos2(100.0, 37.5))]);
+    }
+
+    #[test]
+    fn mouse_buttons_map_to_pointer_buttons() {
+        let pos = egui::pos2(100.0, 37.5);
+        let cases = [
+            (MOUSE_BUTTON_LEFT, egui::PointerButton::Primary),
+            (MOUSE_BUTTON_RIGHT, egui::PointerButton::Secondary),
+            (MOUSE_BUTTON_MIDDLE, egui::PointerButton::Middle),
+            (8, egui::PointerButton::Extra1),
+        ];
+
+        for (vr_button, egui_button) in cases {
+            for pressed in [true, false] {
+                assert_eq!(translate_events(VrEvent::MouseButton { x: 200.0, y: 225.0, button: vr_button, pressed }),
+                           [button(pos, egui_button, pressed)]);
+            }
+        }
+    }
+
+    #[test]
+    fn scrolling_is_scaled_to_points() {
+        assert_eq!(translate_events(VrEvent::Scroll { xdelta: 4.0, ydelta: -1.0 }),
+                   [egui::Event::Scroll(egui::vec2(2.0, -0.5))]);
+    }
+
+    #[test]
+    fn focus_enter_focuses_the_window() {
+        assert_eq!(translate_events(VrEvent::FocusEnter), [egui::Event::WindowFocused(true)]);
+    }
+
+    #[test]
+    fn focus_leave_without_buttons_held_drops_the_pointer() {
+        assert_eq!(translate_events(VrEvent::FocusLeave),
+                   [egui::Event::PointerGone, egui::Event::WindowFocused(false)]);
+    }
+
+    #[test]
+    fn overlay_state_changes() {
+        let state = |event| translate(&event, &surface(), &PointerState::default()).state;
+
+        assert_eq!(state(VrEvent::OverlayShown), Some(OverlayStateChange::Shown));
+        assert_eq!(state(VrEvent::OverlayHidden), Some(OverlayStateChange::Hidden));
+        assert_eq!(state(VrEvent::OverlayClosed), Some(OverlayStateChange::Quit));
+        assert_eq!(state(VrEvent::Quit), Some(OverlayStateChange::Quit));
+        assert_eq!(state(VrEvent::DashboardActivated), None);
+    }
+
+    #[test]
+    fn keyboard_input_becomes_text_and_key_presses() {
+        let press = |key, modifiers| key_press(key, modifiers).to_vec();
+
+        assert_eq!(translate_events(VrEvent::KeyboardInput("héllo".to_string())),
+                   [egui::Event::Text("héllo".to_string())]);
+
+        assert_eq!(translate_events(VrEvent::KeyboardInput("ab\x08c\n".to_string())), [
+            vec![egui::Event::Text("ab".to_string())],
+            press(egui::Key::Backspace, egui::Modifiers::NONE),
+            vec![egui::Event::Text("c".to_string())],
+            press(egui::Key::Enter, egui::Modifiers::NONE),
+        ].concat());
+
+        assert_eq!(translate_events(VrEvent::KeyboardInput("\t\x7f\x1b\x01\x1a".to_string())), [
+            press(egui::Key::Tab, egui::Modifiers::NONE),
+            press(egui::Key::Delete, egui::Modifiers::NONE),
+            press(egui::Key::Escape, egui::Modifiers::NONE),
+            press(egui::Key::A, CTRL),
+            press(egui::Key::Z, CTRL),
+        ].concat());
+    }
+
+    #[test]
+    fn clipboard_shortcuts_become_clipboard_events() {
+        assert_eq!(translate_events(VrEvent::KeyboardInput("\x03a\x18\x16".to_string())), [
+            egui::Event::Copy,
+            egui::Event::Text("a".to_string()),
+            egui::Event::Cut,
+            egui::Event::Paste(String::new()),
+        ]);
+    }
+
+    #[test]
+    fn finishing_with_the_keyboard_releases_focus() {
+        for event in [VrEvent::KeyboardDone, VrEvent::KeyboardClosed] {
+            let translation = translate(&event, &surface(), &PointerState::default());
+            assert!(translation.release_focus);
+            assert!(translation.events.is_empty());
+        }
+    }
+
+    #[test]
+    fn split_characters_are_reassembled_at_every_boundary() {
+        for c in ['é', '€', '🎵'] {
+            let mut buf = [0; 4];
+            let bytes = c.encode_utf8(&mut buf).as_bytes();
+
+            for split in 1..bytes.len() {
+                let mut keyboard = Utf8Assembler::default();
+                assert_eq!(keyboard.push(&bytes[..split]), "", "{:?} split at {}", c, split);
+                assert_eq!(keyboard.push(&bytes[split..]), c.to_string(), "{:?} split at {}", c, split);
+            }
+
+            let mut keyboard = Utf8Assembler::default();
+            let text: String = bytes.iter().map(|&b| keyboard.push(&[b])).collect();
+            assert_eq!(text, c.to_string());
+        }
+    }
+
+    #[test]
+    fn invalid_lead_byte_becomes_replacement_character() {
+        let mut keyboard = Utf8Assembler::default();
+        assert_eq!(keyboard.push(&[0xff, b'a']), "\u{fffd}a");
+        assert_eq!(keyboard.push(b"b"), "b");
+    }
+
+    #[test]
+    fn unfinished_sequence_stays_pending() {
+        let mut keyboard = Utf8Assembler::default();
+        assert_eq!(keyboard.push(b"a\xe2\x82"), "a");
+        assert_eq!(keyboard.pending, [0xe2, 0x82]);
+        assert_eq!(keyboard.push(b"\xac"), "€");
+        assert!(keyboard.pending.is_empty());
+    }
+
+    #[test]
+    fn closing_the_keyboard_drops_unfinished_characters() {
+        for done in [VREvent_KeyboardDone, VREvent_KeyboardClosed] {
+            let mut keyboard = Utf8Assembler::default();
+            assert_eq!(VrEvent::decode(&simulated::keyboard_events(b"\xe2\x82")[0], &mut keyboard),
+                       VrEvent::KeyboardInput(String::new()));
+            VrEvent::decode(&simulated::event(done), &mut keyboard);
+            assert_eq!(VrEvent::decode(&simulated::keyboard_events(b"a")[0], &mut keyboard),
+                       VrEvent::KeyboardInput("a".to_string()));
+        }
+    }
+
+    #[test]
+    fn focus_leave_releases_held_buttons_first() {
+        let mut pointer = PointerState::default();
+        let press = translate(&VrEvent::MouseButton { x: 200.0, y: 225.0, button: MOUSE_BUTTON_LEFT, pressed: true },
+                              &surface(), &pointer);
+        pointer.update(&press.events);
+        let moved = translate(&VrEvent::MouseMove { x: 400.0, y: 150.0 }, &surface(), &pointer);
+        pointer.update(&moved.events);
+
+        let leave = translate(&VrEvent::FocusLeave, &surface(), &pointer);
+        assert_eq!(leave.events, [
+            button(egui::pos2(200.0, 75.0), egui::PointerButton::Primary, false),
+            egui::Event::PointerGone,
+            egui::Event::WindowFocused(false),
+        ]);
+
+        pointer.update(&leave.events);
+        assert_eq!(pointer.pos, None);
+        assert!(pointer.pressed.is_empty());
+    }
+}
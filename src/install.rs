@@ -0,0 +1,209 @@
+use std::path::{Path, PathBuf};
+
+use crate::applications::{Applications, APP_KEY};
+use crate::cli::InstallArgs;
+use crate::config::{self, APP_NAME};
+use crate::manifest::Manifest;
+use crate::systemd;
+use crate::vr::BackgroundSession;
+
+/// Shown by SteamVR next to the overlay's name, written next to the manifest.
+const ICON: &[u8] = include_bytes!("../assets/icon.png");
+
+/// `$XDG_DATA_HOME/mpris-openvr-overlay`, where the manifest and icon that
+/// SteamVR keeps reading for as long as the overlay is installed go.
+fn data_dir() -> Result<PathBuf, String> {
+    config::xdg_dir("XDG_DATA_HOME", ".local/share").
+        map(|dir| dir.join(APP_NAME)).
+        ok_or_else(|| "Neither XDG_DATA_HOME nor HOME is set".to_string())
+}
+
+fn current_binary() -> Result<PathBuf, String> {
+    std::env::current_exe().map_err(|err| format!("Failed to find the overlay binary: {}", err))
+}
+
+/// `path` relative to the current directory, since SteamVR, systemd and
+/// D-Bus start the overlay from elsewhere.
+fn absolute_path(path: &Path, what: &str) -> Result<PathBuf, String> {
+    std::path::absolute(path).map_err(|err| format!("Failed to find the {}: {}", what, err))
+}
+
+fn write_file(path: &Path, contents: &[u8]) -> Result<(), String> {
+    std::fs::write(path, contents).map_err(|err| format!("Failed to write {}: {}", path.display(), err))
+}
+
+fn create_parent(path: &Path) -> Result<(), String> {
+    let dir = path.parent().expect("Installed file has no directory");
+    std::fs::create_dir_all(dir).map_err(|err| format!("Failed to create {}: {}", dir.display(), err))
+}
+
+/// Succeeds if there is no file to remove.
+fn remove_file(path: &Path) -> Result<(), String> {
+    match std::fs::remove_file(path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            Err(format!("Failed to remove {}: {}", path.display(), err))
+        },
+        _ => Ok(()),
+    }
+}
+
+fn applications() -> Result<(BackgroundSession, Applications), String> {
+    let session = BackgroundSession::init().
+        map_err(|err| format!("Failed to connect to SteamVR: {}", err))?;
+    let applications = Applications::get().ok_or("IVRApplications is not available")?;
+
+    Ok((session, applications))
+}
+
+/// Prints the manifest for packages, which install the binary and icon
+/// somewhere else than `install` would.
+pub fn print_manifest(binary_path: Option<&Path>, image_path: Option<&Path>,
+                      config_path: Option<&Path>) -> Result<(), String> {
+    let binary_path = match binary_path {
+        Some(path) => absolute_path(path, "overlay binary")?,
+        None => current_binary()?,
+    };
+    let image_path = image_path.map(|path| absolute_path(path, "icon")).transpose()?;
+    let config_path = config_path.map(|path| absolute_path(path, "configuration")).transpose()?;
+
+    println!("{}", Manifest::new(&binary_path, image_path.as_deref(), config_path.as_deref()).to_json());
+    Ok(())
+}
+
+/// Registers `binary_path` with SteamVR, so that the overlay can be started
+/// from its startup settings, and with `autostart`, whenever SteamVR starts.
+fn install_manifest(binary_path: &Path, config_path: Option<&Path>, autostart: bool) -> Result<(), String> {
+    let (_session, applications) = applications()?;
+
+    let dir = data_dir()?;
+    std::fs::create_dir_all(&dir).map_err(|err| format!("Failed to create {}: {}", dir.display(), err))?;
+
+    let icon_path = dir.join("icon.png");
+    write_file(&icon_path, ICON)?;
+
+    let path = dir.join("manifest.vrmanifest");
+    let manifest = Manifest::new(binary_path, Some(&icon_path), config_path);
+    write_file(&path, manifest.to_json().as_bytes())?;
+
+    applications.add_manifest(&path).
+        map_err(|err| format!("Failed to install {}: {}", path.display(), err))?;
+    println!("Installed {} to start {}", path.display(), binary_path.display());
+
+    if autostart {
+        applications.set_auto_launch(APP_KEY, true).
+            map_err(|err| format!("Failed to enable autostart: {}", err))?;
+        println!("SteamVR will start the overlay along with itself");
+    }
+
+    Ok(())
+}
+
+/// Whether a manifest was installed to remove.
+fn uninstall_manifest() -> Result<bool, String> {
+    let dir = data_dir()?;
+    let path = dir.join("manifest.vrmanifest");
+
+    if !path.exists() {
+        return Ok(false);
+    }
+
+    let (_session, applications) = applications()?;
+    applications.remove_manifest(&path).
+        map_err(|err| format!("Failed to uninstall {}: {}", path.display(), err))?;
+    remove_file(&path)?;
+    remove_file(&dir.join("icon.png"))?;
+
+    println!("Uninstalled {}", path.display());
+    Ok(true)
+}
+
+/// `$XDG_CONFIG_HOME/systemd/user/mpris-openvr-overlay.service`.
+fn unit_path() -> Result<PathBuf, String> {
+    config::xdg_dir("XDG_CONFIG_HOME", ".config").
+        map(|dir| dir.join("systemd").join("user").join(systemd::UNIT_NAME)).
+        ok_or_else(|| "Neither XDG_CONFIG_HOME nor HOME is set".to_string())
+}
+
+/// `$XDG_DATA_HOME/dbus-1/services/<bus name>.service`.
+fn dbus_service_path() -> Result<PathBuf, String> {
+    config::xdg_dir("XDG_DATA_HOME", ".local/share").
+        map(|dir| dir.join("dbus-1").join("services").join(format!("{}.service", systemd::BUS_NAME))).
+        ok_or_else(|| "Neither XDG_DATA_HOME nor HOME is set".to_string())
+}
+
+fn systemctl(args: &[&str]) -> Result<(), String> {
+    let status = std::process::Command::new("systemctl").arg("--user").args(args).status().
+        map_err(|err| format!("Failed to run systemctl: {}", err))?;
+
+    if status.success() { Ok(()) } else { Err(format!("systemctl --user {} failed", args.join(" "))) }
+}
+
+/// Makes the user's systemd start `binary_path` in the background with every
+/// graphical session, starting now, to wait for SteamVR.
+fn install_service(binary_path: &Path, config_path: Option<&Path>, dbus_activation: bool) -> Result<(), String> {
+    let unit_path = unit_path()?;
+    create_parent(&unit_path)?;
+    write_file(&unit_path, systemd::unit(binary_path, config_path, dbus_activation).as_bytes())?;
+    println!("Installed {}", unit_path.display());
+
+    /* Installing again without it takes activation away */
+    let dbus_service_path = dbus_service_path()?;
+    if dbus_activation {
+        create_parent(&dbus_service_path)?;
+        write_file(&dbus_service_path, systemd::dbus_service().as_bytes())?;
+        println!("Installed {}", dbus_service_path.display());
+    }
+    else {
+        remove_file(&dbus_service_path)?;
+    }
+
+    systemctl(&["daemon-reload"])?;
+    systemctl(&["enable", "--now", systemd::UNIT_NAME])?;
+    println!("The overlay now starts with the desktop session");
+
+    Ok(())
+}
+
+/// Whether a unit was installed to remove.
+fn uninstall_service() -> Result<bool, String> {
+    let unit_path = unit_path()?;
+
+    if !unit_path.exists() {
+        return Ok(false);
+    }
+
+    systemctl(&["disable", "--now", systemd::UNIT_NAME])?;
+    remove_file(&unit_path)?;
+    remove_file(&dbus_service_path()?)?;
+    systemctl(&["daemon-reload"])?;
+
+    println!("Uninstalled {}", unit_path.display());
+    Ok(true)
+}
+
+/// Installs the running binary either as a SteamVR application or, with
+/// `--systemd`, as a user service. The overlay is started with `config_path`
+/// if there is one. Installing again updates the paths.
+pub fn install(args: &InstallArgs, config_path: Option<&Path>) -> Result<(), String> {
+    let binary_path = current_binary()?;
+    let config_path = config_path.map(|path| absolute_path(path, "configuration")).transpose()?;
+
+    if args.systemd {
+        install_service(&binary_path, config_path.as_deref(), args.dbus_activation)
+    }
+    else {
+        install_manifest(&binary_path, config_path.as_deref(), args.autostart)
+    }
+}
+
+/// Removes whatever `install` installed.
+pub fn uninstall() -> Result<(), String> {
+    let service = uninstall_service()?;
+    let manifest = uninstall_manifest()?;
+
+    if !service && !manifest {
+        println!("Not installed");
+    }
+
+    Ok(())
+}
@@ -8,71 +8,70 @@ use egui::{
 };
 
 use openvr_sys2::{
-    VROverlayFlags,
-    VROverlayFlags::*,
-    VROverlayInputMethod::*,
-    EVREventType::*
+    VROverlayFlags::*
 };
 
 use gl::types::*;
 
-use std::ffi::CString;
-
-use std::time::Instant;
-
-use freedesktop_icons::lookup as icon_lookup;
-
-const WIDTH: usize = 2048;
-const HEIGHT: usize = 768;
-
-const MOUSE_SCALE: [f32; 2] = [WIDTH as f32, HEIGHT as f32];
-const OVERLAY_WIDTH: f32 = 2.0;
-const UI_SCALE: f32 = 4.2;
-
-fn icon_path(icon_name: &str) -> Option<String> {
-    ["default", "hicolor", "gnome", "oxygen"].iter().find_map(|theme| {
-        let path_buf = icon_lookup(icon_name).with_theme(theme).with_cache().find()?;
-        path_buf.into_os_string().into_string().ok()
-    })
-}
-
-fn icon_uri(icon_name: &str) -> Option<String> {
-    icon_path(icon_name).map(|path| {
-        "file://".to_owned() + &path
-    })
-}
-
-use font_kit::{
-    handle::Handle, source::SystemSource,
+use clap::Parser;
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+mod applications;
+mod backend;
+mod cli;
+mod config;
+mod cursor;
+mod fake;
+mod input;
+mod install;
+mod manifest;
+mod offscreen;
+mod players;
+mod redraw;
+mod settings;
+#[cfg(test)]
+mod simulated;
+#[cfg(test)]
+mod snapshots;
+mod systemd;
+mod ui;
+mod vr;
+
+use applications::{Applications, APP_KEY};
+use backend::MediaBackend;
+use cli::{Cli, Command, RunArgs};
+use config::{Config, ConfigWatcher, OverlayConfig};
+use cursor::Cursor;
+use input::{VrEvent, OverlayStateChange, PointerState, Surface, Utf8Assembler};
+use offscreen::OffscreenRenderer;
+use players::PlayerWorker;
+use redraw::RedrawScheduler;
+use ui::{OverlayUi, icon_path};
+use vr::{
+    VrRuntime, OpenVrRuntime, BackgroundSession,
+    VROverlayFlags_EnableControlBar,
+    VROverlayFlags_EnableControlBarClose,
+    VROverlayFlags_EnableControlBarKeyboard,
 };
 
-fn load_system_font(ctx: &egui::Context) {
-    let mut fonts = egui::FontDefinitions::default();
-
-    let Ok(family) = SystemSource::new().select_family_by_name("sans-serif")
-    else { return };
-
-    for (i, handle) in family.fonts().iter().enumerate() {
-        let name = format!("System Sans Serif {}", i);
-
-        let buf: Vec<u8> = match handle {
-            Handle::Memory { bytes, .. } => bytes.to_vec(),
-            Handle::Path { path, .. } => std::fs::read(path).unwrap(),
-        };
+/// Longest wait for the compositor while the overlay is drawn.
+const FRAME_TIMEOUT_MS: u32 = 20;
 
-        fonts.font_data.insert(
-            name.to_owned(), egui::FontData::from_owned(buf));
+/// Size of the `--desktop` window relative to the overlay texture.
+const DESKTOP_SCALE: f32 = 0.5;
 
-        if let Some(vec) = fonts.families.get_mut(&egui::FontFamily::Proportional) {
-            vec.push(name.to_owned());
-        }
+/// How often `--daemon` checks whether SteamVR started.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
 
-        if let Some(vec) = fonts.families.get_mut(&egui::FontFamily::Monospace) {
-            vec.push(name.to_owned());
-        }
-    }
-
-    ctx.set_fonts(fonts);
+/// Why `run_overlay` returned.
+#[derive(Clone, Copy, PartialEq)]
+enum Exit {
+    /// SteamVR is shutting down.
+    RuntimeQuit,
+    /// The user closed the overlay.
+    Closed,
 }
 
 struct RenderTarget {
@@ -127,8 +126,9 @@ impl PingPongRenderer {
         }
     }
 
+    /// Texture of the target `current_framebuffer` draws into.
     fn current_texture(&self) -> GLuint {
-        self.targets[if self.current_target { 0 } else { 1 }].tex
+        self.targets[if self.current_target { 1 } else { 0 }].tex
     }
 
     fn current_framebuffer(&self) -> GLuint {
@@ -140,160 +140,229 @@ impl PingPongRenderer {
     }
 }
 
-#[allow(non_snake_case)]
-fn VROverlayFlags_EnableControlBar() -> VROverlayFlags { unsafe { std::mem::transmute((1 << 23) as u32) } }
-
-#[allow(non_snake_case)]
-fn VROverlayFlags_EnableControlBarKeyboard() -> VROverlayFlags { unsafe { std::mem::transmute((1 << 24) as u32) } }
+/// Draws the panel with the demo players into `path`.
+fn render_png(path: &Path, config: &Config) {
+    let mut backend = fake::FakeBackend::new(fake::demo_players());
+    let mut renderer = OffscreenRenderer::new(&config.overlay);
 
-#[allow(non_snake_case)]
-fn VROverlayFlags_EnableControlBarClose() -> VROverlayFlags { unsafe { std::mem::transmute((1 << 25) as u32) } }
+    let mut overlay_ui = OverlayUi::new(renderer.context(), config);
+    overlay_ui.update(&mut backend);
 
-fn overlay() -> std::pin::Pin<&'static mut openvr_sys2::IVROverlay> {
-    let overlay_raw = openvr_sys2::VROverlay();
-    if overlay_raw.is_null() {
-        panic!("Failed to obtain handle to VROverlay");
-    }
-    unsafe { std::pin::Pin::new_unchecked(&mut *overlay_raw) }
+    let image = renderer.render(|ctx| { overlay_ui.show(ctx, &mut backend); });
+    image.save(path).expect("Failed to write PNG");
 }
 
-fn poll_vr_event(vr_system: *mut openvr_sys2::IVRSystem) -> Option<openvr_sys2::VREvent_t> {
-    let mut event = std::mem::MaybeUninit::<openvr_sys2::VREvent_t>::uninit();
+fn load_config(path: Option<&Path>) -> Config {
+    let Some(path) = path else { return Config::default() };
 
-    unsafe {
-        let vr = std::pin::Pin::new_unchecked(&mut *vr_system);
-        if vr.PollNextEvent(event.as_mut_ptr() as *mut _,
-                            std::mem::size_of::<openvr_sys2::VREvent_t>() as u32) {
-            return Some(event.assume_init());
+    match Config::load(path) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
         }
     }
+}
 
-    None
+fn watch_config(path: &Path) -> Option<ConfigWatcher> {
+    ConfigWatcher::new(path).
+        map_err(|err| log::warn!("Not watching the configuration for changes: {}", err)).
+        ok()
 }
 
-fn poll_vr_overlay_event(handle:  openvr_sys2::VROverlayHandle_t) -> Option<openvr_sys2::VREvent_t> {
-    let mut event = std::mem::MaybeUninit::<openvr_sys2::VREvent_t>::uninit();
+/// Writes the configuration applied from the settings tab, if there is a
+/// file to write it to.
+fn save_settings(config: &Config, path: Option<&Path>) -> Result<(), String> {
+    let Some(path) = path else { return Ok(()) };
+    config.save(path).map_err(|err| format!("Failed to save {}: {}", path.display(), err))
+}
 
-    unsafe {
-        if overlay().PollNextOverlayEvent(
-            handle, event.as_mut_ptr() as *mut _,
-            std::mem::size_of::<openvr_sys2::VREvent_t>() as u32) {
-            return Some(event.assume_init());
-        }
+fn exit_on_error(result: Result<(), String>) {
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        std::process::exit(1);
     }
+}
 
-    None
+fn yes_no(value: bool) -> &'static str {
+    if value { "yes" } else { "no" }
 }
 
-fn process_vr_event(
-    painter: &Painter, input: &mut RawInput,
-    event: &openvr_sys2::VREvent_t,
-    quit: &mut bool, shown: &mut bool) {
-    let event_type = event.eventType.try_into().unwrap_or(VREvent_None);
-    match event_type {
-        VREvent_Quit => { *quit = true; },
-        VREvent_OverlayClosed => { *quit = true; },
-        VREvent_MouseMove => {
-            input.events.push(
-                egui::Event::PointerMoved(egui::pos2(
-                    unsafe { event.data.mouse.x } / painter.pixels_per_point,
-                    unsafe { HEIGHT as f32 - event.data.mouse.y } / painter.pixels_per_point,
-                )));
-        },
-        VREvent_MouseButtonDown | VREvent_MouseButtonUp => {
-            input.events.push(egui::Event::PointerButton {
-                pos: egui::pos2(
-                    unsafe { event.data.mouse.x } / painter.pixels_per_point,
-                    unsafe { HEIGHT as f32 - event.data.mouse.y } / painter.pixels_per_point,
-                ),
-                button: match unsafe { event.data.mouse.button } {
-                    1 => egui::PointerButton::Primary,
-                    2 => egui::PointerButton::Secondary,
-                    3 => egui::PointerButton::Middle,
-                    _ => egui::PointerButton::Extra1,
-                },
-                pressed: event_type == VREvent_MouseButtonDown,
-                modifiers: egui::Modifiers::NONE
-            });
+/// Prints one line per player, with tab-separated fields for scripts.
+fn list_players(config: &Config) {
+    let players = players::list_players(&config.players).unwrap_or_else(|err| {
+        eprintln!("Failed to connect to D-Bus: {}", err);
+        std::process::exit(1);
+    });
+
+    for player in players {
+        let metadata = &player.metadata;
+        println!("{}\t{}\t{:?}\t{}\t{}",
+                 player.bus_name, player.identity, player.playback_status,
+                 metadata.artists().map(|x| x.join(", ")).unwrap_or_default(),
+                 metadata.title().unwrap_or(""));
+    }
+}
+
+/// Prints what the overlay would find if it started now.
+fn status(config_path: Option<&Path>) {
+    let config = match config_path {
+        Some(path) => match Config::load(path) {
+            Ok(config) => {
+                if path.exists() {
+                    println!("Configuration: {}", path.display());
+                }
+                else {
+                    println!("Configuration: defaults, {} does not exist", path.display());
+                }
+                config
+            },
+            Err(err) => {
+                println!("Configuration: {}", err);
+                Config::default()
+            },
         },
-        VREvent_ScrollDiscrete | VREvent_ScrollSmooth => {
-            input.events.push(egui::Event::Scroll(
-                egui::vec2(
-                    unsafe { event.data.scroll.xdelta } / painter.pixels_per_point,
-                    unsafe { event.data.scroll.ydelta } / painter.pixels_per_point
-                )
-            ));
+        None => {
+            println!("Configuration: defaults, no home directory");
+            Config::default()
         },
-        VREvent_KeyboardCharInput => {
-            let mut buffer: [u8; 12] = [0; 12];
-            for (i, &x) in unsafe { std::ptr::addr_of!(event.data.keyboard).read_unaligned().cNewInput }.iter().enumerate() {
-                buffer[i] = x as u8;
-            }
+    };
 
-            if let Ok(cstr) = std::ffi::CStr::from_bytes_until_nul(&buffer) {
-                if let Ok(string) = cstr.to_str() {
-                    if string == "\n" {
-                        for &val in [true, false].iter() {
-                            input.events.push(egui::Event::Key {
-                                key: egui::Key::Enter,
-                                pressed: val,
-                                repeat: false,
-                                modifiers: egui::Modifiers::NONE
-                            });
-                        }
-                    }
-                    else if string == "\x08" { // Backspace control character
-                        for &val in [true, false].iter() {
-                            input.events.push(egui::Event::Key {
-                                key: egui::Key::Backspace,
-                                pressed: val,
-                                repeat: false,
-                                modifiers: egui::Modifiers::NONE
-                            });
-                        }
-                    }
-                    else {
-                        input.events.push(egui::Event::Text(string.to_string()))
-                    }
-                }
+    match BackgroundSession::init() {
+        Ok(_session) => {
+            println!("SteamVR: running");
+
+            if let Some(applications) = Applications::get() {
+                println!("Installed: {}", yes_no(applications.is_installed(APP_KEY)));
+                println!("Autostart: {}", yes_no(applications.auto_launch(APP_KEY)));
             }
         },
-        VREvent_FocusEnter => { input.events.push(egui::Event::WindowFocused(true)); },
-        VREvent_FocusLeave => { input.events.push(egui::Event::WindowFocused(false)); },
-        VREvent_OverlayShown => { *shown = true; }
-        VREvent_OverlayHidden => { *shown = false; }
+        Err(err) => println!("SteamVR: not running ({})", err),
+    }
 
-        _ => ()
+    match players::list_players(&config.players) {
+        Ok(players) => {
+            let identities: Vec<_> = players.iter().map(|p| p.identity.as_str()).collect();
+            println!("Media players: {}", identities.join(", "));
+        },
+        Err(err) => println!("Media players: D-Bus is not available ({})", err),
     }
 }
 
-fn find_players(finder: &mpris::PlayerFinder) -> Vec<mpris::Player> {
-    finder.
-        iter_players().map(|x| x.flatten().collect()).
-        unwrap_or_else(|_| vec![])
+/// Retries until SteamVR runs, logging why it is not available whenever
+/// that changes.
+fn wait_for_openvr(overlay: &OverlayConfig) -> OpenVrRuntime {
+    let mut last_error = None;
+
+    loop {
+        match OpenVrRuntime::init_if_running(&overlay.key, &overlay.name) {
+            Ok(runtime) => {
+                log::info!("Connected to SteamVR");
+                systemd::notify_status("Running in SteamVR");
+                return runtime;
+            },
+            Err(err) => {
+                if last_error != Some(err) {
+                    log::warn!("Waiting for SteamVR: {}", err);
+                    systemd::notify_status(&format!("Waiting for SteamVR: {}", err));
+                }
+                last_error = Some(err);
+            },
+        }
+
+        std::thread::sleep(RETRY_INTERVAL);
+    }
 }
 
-fn main() {
-    let finder = mpris::PlayerFinder::new().expect("Failed to connect to DBus MediaPlayer2");
+/// Runs the overlay in SteamVR, or in a window with `--desktop`. With
+/// `--daemon`, keeps running across SteamVR sessions until the user closes
+/// the overlay.
+fn run(args: &RunArgs, config_path: Option<&Path>) {
+    let mut config = load_config(config_path);
 
-    let mut players: Vec<_> = find_players(&finder);
-    let mut last_players_lookup = Instant::now();
+    if args.desktop {
+        run_desktop(&mut PlayerWorker::spawn(&config.players), &config, config_path);
+        return;
+    }
 
-    let sdl = sdl2::init().expect("Failed to initialize SDL");
+    let mut backend = PlayerWorker::spawn(&config.players);
 
-    let mut error = openvr_sys2::EVRInitError::VRInitError_None;
-    let vr_system = unsafe {
-        openvr_sys2::VR_Init(
-            &mut error,
-            openvr_sys2::EVRApplicationType::VRApplication_Overlay,
-            std::ptr::null())
-    };
+    if args.daemon {
+        systemd::notify_ready();
+        if args.dbus_activation {
+            systemd::spawn_bus_name_owner();
+        }
+    }
+
+    loop {
+        let mut runtime = if args.daemon {
+            wait_for_openvr(&config.overlay)
+        }
+        else {
+            OpenVrRuntime::init(&config.overlay.key, &config.overlay.name).unwrap_or_else(|err| {
+                eprintln!("Failed to initialize OpenVR: {}", err);
+                std::process::exit(1);
+            })
+        };
+
+        let exit = run_overlay(&mut runtime, &mut backend, &config, config_path);
+
+        /* Shuts OpenVR down before waiting for the next session */
+        drop(runtime);
+
+        if !args.daemon || exit == Exit::Closed {
+            return;
+        }
+
+        log::info!("SteamVR quit, waiting for it to start again");
 
-    if vr_system.is_null() {
-        panic!("Failed to initialize OpenVR");
+        /* SteamVR is still shutting down, and would accept a new overlay for a moment */
+        std::thread::sleep(RETRY_INTERVAL);
+
+        /* The file may have changed since the start, with or without the overlay watching it */
+        if let Some(path) = config_path {
+            match Config::load(path) {
+                Ok(new_config) => {
+                    if new_config.players.worker_differs(&config.players) {
+                        backend.reconfigure(&new_config.players);
+                    }
+                    config = new_config;
+                },
+                Err(err) => log::warn!("{}, keeping the previous configuration", err),
+            }
+        }
     }
+}
 
-    let sdl_video = sdl.video().expect("Failed to initialize SDL Video");
+fn main() {
+    let cli = Cli::parse();
+
+    /* RUST_LOG comes second so that it can refine the level per module */
+    env_logger::Builder::new().
+        filter_level(cli.log_level).
+        parse_default_env().
+        init();
+
+    /* Only passed on to the installed overlay if given */
+    let explicit_config_path = cli.config.as_deref();
+    let config_path = cli.config.clone().or_else(config::default_path);
+    let config_path = config_path.as_deref();
+
+    match cli.command.unwrap_or(Command::Run(cli.run)) {
+        Command::Run(args) => run(&args, config_path),
+        Command::Install(args) => exit_on_error(install::install(&args, explicit_config_path)),
+        Command::Uninstall => exit_on_error(install::uninstall()),
+        Command::Manifest { binary, image } => {
+            exit_on_error(install::print_manifest(binary.as_deref(), image.as_deref(), explicit_config_path));
+        },
+        Command::ListPlayers => list_players(&load_config(config_path)),
+        Command::Status => status(config_path),
+        Command::RenderPng { path } => render_png(&path, &load_config(config_path)),
+    }
+}
+
+fn open_gl_window(sdl_video: &sdl2::VideoSubsystem,
+                  builder: &mut sdl2::video::WindowBuilder) -> sdl2::video::Window {
     let gl_attr = sdl_video.gl_attr();
     gl_attr.set_context_profile(sdl2::video::GLProfile::Core);
     gl_attr.set_context_major_version(3);
@@ -303,49 +372,161 @@ fn main() {
     gl_attr.set_multisample_samples(0);
     gl_attr.set_share_with_current_context(true);
 
-    let window = sdl_video.window("mpris-openvr-overlay", 128, 128).
-        opengl().hidden().build().expect("Failed to open window");
+    builder.opengl().build().expect("Failed to open window")
+}
+
+/// Shows the panel in a regular window driven by the desktop mouse and
+/// keyboard, without touching OpenVR. Settings applied in the window are
+/// saved to `config_path`.
+fn run_desktop(backend: &mut dyn MediaBackend, config: &Config, config_path: Option<&Path>) {
+    let overlay = &config.overlay;
+
+    let sdl = sdl2::init().expect("Failed to initialize SDL");
 
+    let sdl_video = sdl.video().expect("Failed to initialize SDL Video");
+    let window = open_gl_window(&sdl_video, &mut sdl_video.window(
+        "mpris-openvr-overlay",
+        (overlay.width as f32 * DESKTOP_SCALE) as u32,
+        (overlay.height as f32 * DESKTOP_SCALE) as u32));
     let _context = window.gl_create_context().expect("Failed to create OpenGL context");
 
     gl::load_with(|s| sdl_video.gl_get_proc_address(s) as *const _);
 
-    let mut overlay_handle_slot   = std::mem::MaybeUninit::<openvr_sys2::VROverlayHandle_t>::uninit();
-    let mut thumbnail_handle_slot = std::mem::MaybeUninit::<openvr_sys2::VROverlayHandle_t>::uninit();
-    unsafe {
-        let key = CString::new("mpris-openvr-overlay").unwrap();
-        let name = CString::new("Media Player").unwrap();
-
-        overlay().CreateDashboardOverlay(
-            key.as_ptr() as *const _,
-            name.as_ptr() as *const _,
-            overlay_handle_slot.as_mut_ptr(),
-            thumbnail_handle_slot.as_mut_ptr());
-    }
+    let egui_ctxt = egui::Context::default();
+    egui_extras::install_image_loaders(&egui_ctxt);
+
+    let mut overlay_ui = OverlayUi::new(&egui_ctxt, config);
+    let mut players_config = config.players.clone();
+
+    /* Same size in points as the overlay, so the layout matches */
+    let (mut painter, mut egui_state) =
+        egui_sdl2_gl::with_sdl2(&window, ShaderVersion::Default,
+                                DpiScaling::Custom(overlay.ui_scale as f32 * DESKTOP_SCALE));
 
-    let overlay_handle   = unsafe { overlay_handle_slot.assume_init() };
-    let thumbnail_handle = unsafe { thumbnail_handle_slot.assume_init() };
+    let mut quit = false;
+
+    let mut event_pump = sdl.event_pump().expect("Failed to acquire events");
+
+    let start_time = Instant::now();
+
+    while !quit {
+        overlay_ui.update(backend);
+
+        egui_state.input.time = Some(start_time.elapsed().as_secs_f64());
+        egui_ctxt.begin_frame(egui_state.input.take());
+
+        /* The window keeps its size; only the rest of the settings apply here */
+        if let Some(new_config) = overlay_ui.show(&egui_ctxt, backend) {
+            overlay_ui.settings.set_error(save_settings(&new_config, config_path).err());
+            overlay_ui.set_config(&egui_ctxt, &new_config);
+
+            if new_config.players.worker_differs(&players_config) {
+                backend.reconfigure(&new_config.players);
+            }
+            players_config = new_config.players;
+        }
+
+        let FullOutput {
+            platform_output,
+            repaint_after: _,
+            textures_delta,
+            shapes,
+        } = egui_ctxt.end_frame();
+
+        egui_state.process_output(&window, &platform_output);
+
+        let paint_jobs = egui_ctxt.tessellate(shapes);
 
-    if let Some(path) = icon_path("multimedia-player").and_then(|p| CString::new(p).ok()) {
         unsafe {
-            overlay().SetOverlayFromFile(thumbnail_handle, path.as_ptr());
+            gl::ClearColor(0.0, 0.0, 0.0, 1.0);
+            gl::Clear(gl::COLOR_BUFFER_BIT);
+        }
+
+        painter.paint_jobs(None, textures_delta, paint_jobs);
+        window.gl_swap_window();
+
+        /* Wake up at the overlay's frame rate to keep the position moving */
+        if let Some(event) = event_pump.wait_event_timeout(20) {
+            for event in std::iter::once(event).chain(event_pump.poll_iter()) {
+                match event {
+                    sdl2::event::Event::Quit { .. } => { quit = true; },
+                    _ => egui_state.process_input(&window, event, &mut painter),
+                }
+            }
         }
     }
+}
 
-    overlay().SetOverlayInputMethod(overlay_handle, VROverlayInputMethod_Mouse);
+/// Sets the overlay properties that come from the configuration.
+fn apply_overlay_settings(runtime: &mut dyn VrRuntime, overlay: &OverlayConfig) {
+    runtime.set_flag(VROverlayFlags_EnableControlBar(), true);
+    runtime.set_flag(VROverlayFlags_EnableControlBarClose(), true);
+    runtime.set_flag(VROverlayFlags_EnableControlBarKeyboard(), true);
+    runtime.set_flag(VROverlayFlags_SendVRSmoothScrollEvents, true);
 
-    overlay().SetOverlayFlag(overlay_handle, VROverlayFlags_EnableControlBar(), true);
-    overlay().SetOverlayFlag(overlay_handle, VROverlayFlags_EnableControlBarClose(), true);
-    overlay().SetOverlayFlag(overlay_handle, VROverlayFlags_EnableControlBarKeyboard(), true);
-    overlay().SetOverlayFlag(overlay_handle, VROverlayFlags_SendVRSmoothScrollEvents, true);
+    runtime.set_mouse_scale(overlay.mouse_scale());
+    runtime.set_width_in_meters(overlay.width_in_meters as f32);
+}
 
-    unsafe {
-        overlay().SetOverlayMouseScale(overlay_handle, MOUSE_SCALE.as_ptr() as *const _);
+/// Sizes the painter for the overlay texture and returns how the overlay's
+/// input maps onto it.
+fn resize_painter(painter: &mut Painter, overlay: &OverlayConfig) -> Surface {
+    painter.pixels_per_point = overlay.ui_scale as f32;
+    painter.update_screen_rect((overlay.width as u32, overlay.height as u32));
+
+    Surface {
+        mouse_scale: overlay.mouse_scale(),
+        size: painter.screen_rect.size(),
+        pixels_per_point: painter.pixels_per_point,
     }
+}
 
-    overlay().SetOverlayWidthInMeters(overlay_handle, OVERLAY_WIDTH);
+/// Opens the SteamVR keyboard when a text field gains focus and hides it once
+/// none has. `shown_for` is the field it was last opened for.
+fn update_keyboard(runtime: &mut dyn VrRuntime, shown_for: &mut Option<egui::Id>, focused: Option<egui::Id>) {
+    match focused {
+        Some(id) => {
+            if *shown_for != Some(id) {
+                runtime.show_keyboard();
+                *shown_for = Some(id);
+            }
+        },
+        None => {
+            if shown_for.take().is_some() {
+                runtime.hide_keyboard();
+            }
+        },
+    }
+}
+
+/// Runs the overlay until SteamVR or the user closes it. With a
+/// `config_path`, changes to the file are applied as they are saved and
+/// settings applied in the overlay are written to it.
+fn run_overlay(runtime: &mut dyn VrRuntime, backend: &mut dyn MediaBackend, config: &Config,
+               config_path: Option<&Path>) -> Exit {
+    let mut config = config.clone();
+    let mut watcher = config_path.and_then(watch_config);
+
+    let sdl = sdl2::init().expect("Failed to initialize SDL");
+
+    let sdl_video = sdl.video().expect("Failed to initialize SDL Video");
+    let window = open_gl_window(&sdl_video, sdl_video.window("mpris-openvr-overlay", 128, 128).hidden());
+    let _context = window.gl_create_context().expect("Failed to create OpenGL context");
 
-    let mut renderer = PingPongRenderer::new(WIDTH, HEIGHT);
+    gl::load_with(|s| sdl_video.gl_get_proc_address(s) as *const _);
+
+    if let Some(path) = icon_path("multimedia-player") {
+        runtime.set_thumbnail(&path);
+    }
+
+    runtime.set_input_method_mouse();
+    apply_overlay_settings(runtime, &config.overlay);
+
+    if let Some(autostart) = config.overlay.autostart {
+        runtime.set_autostart(autostart);
+    }
+
+    let mut renderer = PingPongRenderer::new(config.overlay.width, config.overlay.height);
 
     unsafe {
         gl::Disable(gl::DEPTH_TEST);
@@ -354,10 +535,13 @@ fn main() {
     let egui_ctxt = egui::Context::default();
     egui_extras::install_image_loaders(&egui_ctxt);
 
-    println!("{:?}", egui_ctxt.style());
-    let (mut painter, mut egui_state) =
-        egui_sdl2_gl::with_sdl2(&window, ShaderVersion::Default, DpiScaling::Custom(UI_SCALE));
-    painter.update_screen_rect((WIDTH as u32, HEIGHT as u32));
+    let mut overlay_ui = OverlayUi::new(&egui_ctxt, &config);
+
+    log::debug!("{:?}", egui_ctxt.style());
+    /* Not through egui_sdl2_gl::with_sdl2, which needs system cursors that
+     * SDL's offscreen driver does not have */
+    let mut painter = Painter::new(&window, config.overlay.ui_scale as f32, ShaderVersion::Default);
+    let mut surface = resize_painter(&mut painter, &config.overlay);
 
     let mut egui_input = RawInput {
         screen_rect: Some(painter.screen_rect),
@@ -366,223 +550,101 @@ fn main() {
     };
 
 
-    let mut quit = false;
+    let mut exit = None;
 
     let mut event_pump = sdl.event_pump().expect("Failed to acquire events");
 
     let start_time = Instant::now();
 
-    let mut selected_player_id = 0;
+    /* Nothing is drawn until the overlay is first opened in the dashboard */
+    let mut shown = runtime.is_visible();
 
-    let mut metadata = None;
-    let mut metadata_last_lookup = Instant::now();
+    let mut keyboard = Utf8Assembler::default();
+    let mut pointer = PointerState::default();
+    let mut release_focus = false;
+    let mut keyboard_shown_for = None;
 
-    let mut previous_id = selected_player_id;
+    /* Where the laser last hit the overlay, in overlay mouse coordinates */
+    let mut laser = None;
+    let mut cursor = None;
+    let mut cursor_position = None;
 
-    let mut shown = true;
+    let redraw = RedrawScheduler::new();
+    {
+        /* Images finish loading on other threads */
+        let redraw = redraw.clone();
+        egui_ctxt.set_request_repaint_callback(move |info| redraw.request_after(info.after));
+    }
 
-    // load_system_font(&egui_ctxt);
+    loop {
+        if let Some(exit) = exit {
+            return exit;
+        }
 
-    while !quit {
-        if last_players_lookup.elapsed().as_secs_f64() > 3.0 {
-            let old_player_bus_name = if selected_player_id >= players.len() {
+        /* From the file changing, or from the settings tab */
+        let mut new_config = match watcher.as_mut().and_then(ConfigWatcher::reload) {
+            Some(Ok(new_config)) => Some(new_config),
+            Some(Err(err)) => {
+                log::warn!("{}, keeping the previous configuration", err);
                 None
-            } else {
-                Some(players[selected_player_id].bus_name().to_string())
-            };
-
-            players = find_players(&finder);
-            last_players_lookup = Instant::now();
-
-            selected_player_id = 0;
+            },
+            None => None
+        };
 
-            if let Some(bus) = old_player_bus_name {
-                if let Some(id) = players.iter().position(|p| p.bus_name() == bus) {
-                    selected_player_id = id;
-                }
-            }
+        if overlay_ui.update(backend) {
+            redraw.request();
         }
 
-        egui_state.input.time = Some(start_time.elapsed().as_secs_f64());
-        egui_ctxt.begin_frame(egui_input.take());
+        if shown && redraw.take_due() {
+            egui_input.time = Some(start_time.elapsed().as_secs_f64());
+            egui_ctxt.begin_frame(egui_input.take());
 
-        if shown && !players.is_empty() {
-            if selected_player_id >= players.len() {
-                selected_player_id = players.len() - 1;
+            /* After starting the frame, so the text field sees that it lost focus */
+            if release_focus {
+                egui_ctxt.memory_mut(|memory| memory.stop_text_input());
+                release_focus = false;
             }
 
-            let selected_player = &players[selected_player_id];
-            let mut volume = selected_player.get_volume().unwrap_or(1.0);
-            let old_volume = volume;
-
-            if metadata_last_lookup.elapsed().as_secs_f64() >= 1.0 || previous_id != selected_player_id {
-                metadata = selected_player.get_metadata().ok();
-                metadata_last_lookup = Instant::now();
-                previous_id = selected_player_id;
+            if let Some(applied) = overlay_ui.show(&egui_ctxt, backend) {
+                overlay_ui.settings.set_error(save_settings(&applied, config_path).err());
+                new_config = Some(applied);
             }
 
-            if let Some(metadata) = metadata.as_ref() {
-                egui::SidePanel::left("icon").show(&egui_ctxt, |ui| {
-                    if let Some(url) = metadata.art_url() {
-                        ui.add(egui::Image::new(url).show_loading_spinner(true).shrink_to_fit());
-                    }
-                });
-            }
+            let FullOutput {
+                platform_output,
+                repaint_after,
+                textures_delta,
+                shapes,
+            } = egui_ctxt.end_frame();
 
-            egui::CentralPanel::default().show(&egui_ctxt, |ui| {
-                egui::ScrollArea::horizontal().show(ui, |ui| {
-                    egui::ComboBox::from_label("Player").
-                        selected_text(players[selected_player_id].identity()).
-                        show_index(
-                            ui,
-                            &mut selected_player_id,
-                            players.len(),
-                            |i| players[i].identity()
-                        );
-                });
-                ui.separator();
-
-                if let Some(metadata) = metadata.as_ref() {
-                    let song_name = metadata.title().unwrap_or("?");
-                    let artists = metadata.artists().map(|x| x.join(", ")).map(|x| {
-                        if x.is_empty() { x } else { x + " - " }
-                    }).unwrap_or("".to_string());
-                    let shown_name = artists + song_name;
-                    ui.label(shown_name);
-                }
-
-                if selected_player.has_volume().unwrap_or(false) {
-                    ui.horizontal(|ui| {
-                        ui.label("Volume");
-                        ui.add(egui::Slider::new(&mut volume, 0.0..=1.0).
-                               show_value(false).
-                               trailing_fill(true));
-                    });
-                }
+            redraw.request_after(repaint_after);
 
-                if selected_player.has_position().unwrap_or(false) {
-                    if let Some(metadata) = metadata.as_ref() {
-                        let pos = selected_player.get_position().unwrap_or(std::time::Duration::ZERO);
-                        let duration = metadata.length().unwrap_or(std::time::Duration::ZERO);
-                        let mut out_pos = pos.as_secs_f64();
-
-                        ui.horizontal(|ui| {
-                            let time_formatter = |x, _| {
-                                let total_secs = x as u64;
-
-                                let secs = total_secs % 60;
-                                let minutes = (total_secs / 60) % 60;
-                                let hours = (total_secs / 60) / 60;
-
-                                if hours == 0 {
-                                    format!("{:0>2}:{:0>2}", minutes, secs)
-                                }
-                                else {
-                                    format!("{:0>2}:{:0>2}:{:0>2}", hours, minutes, secs)
-                                }
-                            };
-
-                            ui.add(egui::Slider::new(&mut out_pos, 0.0..=duration.as_secs_f64()).
-                                   custom_formatter(time_formatter).
-                                   trailing_fill(true));
-                            ui.label(time_formatter(duration.as_secs_f64(), 0..=0));
-                        });
-
-                        if out_pos != pos.as_secs_f64() {
-                            if let Some(id) = metadata.track_id() {
-                                let _ = selected_player.set_position(id, &std::time::Duration::from_secs_f64(out_pos));
-                            }
-                        }
-                    }
-                }
-
-                if volume != old_volume {
-                    let _ = selected_player.set_volume(volume);
-                }
-
-                ui.separator();
-
-                ui.horizontal(|ui| {
-                    let pause = selected_player.get_playback_status().
-                        unwrap_or(mpris::PlaybackStatus::Stopped) ==
-                        mpris::PlaybackStatus::Playing;
-
-                    if let Some(icon) = icon_uri(if pause  { "media-playback-pause" } else { "media-playback-start" }) {
-                        if ui.add(egui::ImageButton::new(egui::Image::from_uri(icon))).clicked() {
-                            if pause {
-                                let _ = selected_player.pause();
-                            }
-                            else {
-                                let _ = selected_player.play();
-                            }
-                        }
-                    }
-
-                    if selected_player.can_go_previous().unwrap_or(false) {
-                        if let Some(icon) = icon_uri("media-skip-backward") {
-                            if ui.add(egui::ImageButton::new(icon)).clicked() {
-                                let _ = selected_player.previous();
-                            }
-                        }
-                    }
-
-                    if selected_player.can_stop().unwrap_or(false) {
-                        if let Some(icon) = icon_uri("media-playback-stop") {
-                            if ui.add(egui::ImageButton::new(icon)).clicked() {
-                                let _ = selected_player.stop();
-                            }
-                        }
-                    }
+            if !platform_output.copied_text.is_empty() {
+                let _ = sdl_video.clipboard().set_clipboard_text(&platform_output.copied_text);
+            }
 
-                    if selected_player.can_go_next().unwrap_or(false) {
-                        if let Some(icon) = icon_uri("media-skip-forward") {
-                            if ui.add(egui::ImageButton::new(icon)).clicked() {
-                                let _ = selected_player.next();
-                            }
-                        }
-                    }
+            update_keyboard(runtime, &mut keyboard_shown_for, ui::focused_text_field(&egui_ctxt, &platform_output));
 
-                    if selected_player.can_shuffle().unwrap_or(false) {
-                        let shuffle_state = selected_player.get_shuffle().unwrap_or(false);
-                        if let Some(icon) = icon_uri("media-playlist-shuffle") {
-                            if ui.add(egui::ImageButton::new(icon).selected(shuffle_state)).clicked() {
-                                let _ = selected_player.set_shuffle(!shuffle_state);
-                            }
-                        }
-                    }
+            let new_cursor = Cursor::from_egui(platform_output.cursor_icon);
+            if new_cursor != cursor {
+                runtime.set_cursor(new_cursor);
+                cursor = new_cursor;
+            }
 
-                    if selected_player.can_loop().unwrap_or(false) {
-                        let loop_state = selected_player.get_loop_status().unwrap_or(mpris::LoopStatus::None);
-                        if let Some(icon) = icon_uri("media-playlist-repeat") {
-                            if ui.add(egui::ImageButton::new(icon).selected(loop_state != mpris::LoopStatus::None)).clicked() {
-                                if loop_state == mpris::LoopStatus::None {
-                                    let _ = selected_player.set_loop_status(mpris::LoopStatus::Track);
-                                }
-                                else {
-                                    let _ = selected_player.set_loop_status(mpris::LoopStatus::None);
-                                }
-                            }
-                        }
-                    }
+            let new_cursor_position = cursor.zip(laser).
+                map(|(cursor, laser)| {
+                    cursor.position_for(laser, surface.mouse_scale[0] / config.overlay.width_in_meters as f32)
                 });
-            });
-        }
-
-        let FullOutput {
-            platform_output,
-            repaint_after: _,
-            textures_delta,
-            shapes,
-        } = egui_ctxt.end_frame();
-
-        egui_state.process_output(&window, &platform_output);
+            if new_cursor_position != cursor_position {
+                runtime.set_cursor_position(new_cursor_position);
+                cursor_position = new_cursor_position;
+            }
 
-        if shown {
             let paint_jobs = egui_ctxt.tessellate(shapes);
 
             unsafe {
                 gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, renderer.current_framebuffer());
-                gl::Viewport(0, 0, WIDTH as i32, HEIGHT as i32);
+                gl::Viewport(0, 0, config.overlay.width as i32, config.overlay.height as i32);
                 gl::DrawBuffer(gl::COLOR_ATTACHMENT0);
 
                 gl::ClearColor(0.0, 0.0, 0.0, 0.0);
@@ -591,47 +653,245 @@ fn main() {
 
             painter.paint_jobs(None, textures_delta, paint_jobs);
 
-            let bounds = openvr_sys2::VRTextureBounds_t {
-                uMin: 0.0, vMin: 0.0, uMax: 1.0, vMax: 1.0
-            };
+            unsafe {
+                gl::Flush();
+            }
+
+            /* The overlay keeps showing this texture until the next redraw,
+             * while the other one gets drawn into */
+            runtime.submit_texture(renderer.current_texture());
+            renderer.flip();
+        }
+
+        /* Saving from the settings tab also reloads the file, which changes nothing */
+        if let Some(new_config) = new_config.filter(|new_config| *new_config != config) {
+            let (old, new) = (&config.overlay, &new_config.overlay);
+
+            if new.key != old.key || new.name != old.name {
+                log::warn!("overlay.key and overlay.name only change after a restart");
+            }
+
+            if (new.width, new.height) != (old.width, old.height) {
+                renderer = PingPongRenderer::new(new.width, new.height);
+            }
+
+            apply_overlay_settings(runtime, new);
+            surface = resize_painter(&mut painter, new);
+            egui_input.screen_rect = Some(painter.screen_rect);
+            egui_input.pixels_per_point = Some(painter.pixels_per_point);
+
+            if new.autostart != old.autostart {
+                if let Some(autostart) = new.autostart {
+                    runtime.set_autostart(autostart);
+                }
+            }
+
+            if new_config.players.worker_differs(&config.players) {
+                backend.reconfigure(&new_config.players);
+            }
 
-            let texture = openvr_sys2::Texture_t {
-                eType: openvr_sys2::ETextureType::TextureType_OpenGL,
-                /* egui_sdl2_gl renders with FRAMEBUFFER_SRGB enabled */
-                eColorSpace: openvr_sys2::EColorSpace::ColorSpace_Gamma,
-                handle: renderer.current_texture() as usize as *mut std::ffi::c_void
-            };
+            overlay_ui.set_config(&egui_ctxt, &new_config);
 
-            unsafe { overlay().SetOverlayTexture(overlay_handle, &texture); };
-            unsafe { overlay().SetOverlayTextureBounds(overlay_handle, &bounds); };
+            config = new_config;
+            redraw.request();
         }
 
         for event in event_pump.poll_iter() {
-            match event {
-                sdl2::event::Event::Quit { .. } => { quit = true; },
+            if let sdl2::event::Event::Quit { .. } = event {
+                exit = Some(Exit::Closed);
+            }
+        }
+
+        while let Some(event) = runtime.poll_system_event() {
+            /* Typing only arrives as overlay events */
+            match VrEvent::decode(&event, &mut Utf8Assembler::default()) {
+                VrEvent::Quit => {
+                    runtime.acknowledge_quit();
+                    exit = Some(Exit::RuntimeQuit);
+                },
+                /* The dashboard may reopen on this overlay without it being shown again */
+                VrEvent::DashboardActivated if !shown && runtime.is_visible() => {
+                    shown = true;
+                    redraw.request();
+                },
                 _ => ()
             }
         }
 
-        while let Some(event) = poll_vr_event(vr_system) {
-            match event.eventType.try_into().unwrap_or(VREvent_None) {
-                VREvent_Quit => { quit = true; },
+        while let Some(event) = runtime.poll_overlay_event() {
+            let event = VrEvent::decode(&event, &mut keyboard);
+
+            match event {
+                VrEvent::MouseMove { x, y } | VrEvent::MouseButton { x, y, .. } => {
+                    laser = Some([x, y]);
+                },
+                VrEvent::FocusLeave => { laser = None; },
                 _ => ()
             }
+
+            let mut translation = input::translate(&event, &surface, &pointer);
+            for event in translation.events.iter_mut() {
+                if let egui::Event::Paste(text) = event {
+                    *text = sdl_video.clipboard().clipboard_text().unwrap_or_default();
+                }
+            }
+
+            pointer.update(&translation.events);
+            egui_input.events.extend(translation.events);
+            release_focus |= translation.release_focus;
+
+            match translation.state {
+                Some(OverlayStateChange::Quit) => {
+                    if event == VrEvent::Quit {
+                        runtime.acknowledge_quit();
+                        exit = Some(Exit::RuntimeQuit);
+                    }
+                    else {
+                        exit = Some(Exit::Closed);
+                    }
+                },
+                Some(OverlayStateChange::Shown) => { shown = true; },
+                Some(OverlayStateChange::Hidden) => { shown = false; },
+                None => ()
+            }
+
+            if event != VrEvent::Other {
+                redraw.request();
+            }
         }
 
-        while let Some(event) = poll_vr_overlay_event(overlay_handle){
-            process_vr_event(&painter, &mut egui_input, &event,
-                             &mut quit, &mut shown);
+        if exit.is_none() && !runtime.is_running() {
+            log::warn!("SteamVR went away without quitting");
+            exit = Some(Exit::RuntimeQuit);
         }
 
-        unsafe {
-            gl::Flush();
+        if shown {
+            runtime.wait_frame_sync(FRAME_TIMEOUT_MS);
         }
+        else {
+            runtime.wait_events(config.overlay.hidden_poll_interval_ms);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use backend::PlayerCommand;
+    use openvr_sys2::EVREventType::*;
+    use simulated::{OverlayChange, SimulatedRuntime};
 
-        renderer.flip();
-        overlay().WaitFrameSync(20);
+    /// Players for the simulated session, both paused with every capability.
+    fn simulated_players() -> fake::FakeBackend {
+        fake::FakeBackend::new(vec![
+            fake::fake_player("first", "First Player"),
+            fake::fake_player("second", "Second Player"),
+        ])
     }
 
-    openvr_sys2::VR_Shutdown();
+    /// Focus, a click in the middle of the overlay, a scroll, typing, hiding and
+    /// showing the overlay, the laser leaving it and SteamVR quitting.
+    fn simulated_session(config: &Config) -> SimulatedRuntime {
+        let [x, y] = config.overlay.mouse_scale().map(|size| size / 2.0);
+
+        let mut runtime = SimulatedRuntime::new();
+        runtime.push_frame(vec![simulated::event(VREvent_FocusEnter)]);
+        runtime.push_frame(simulated::click_events(x, y, input::MOUSE_BUTTON_LEFT));
+        runtime.push_frame(vec![simulated::scroll_event(0.0, -1.0)]);
+        runtime.push_frame(simulated::keyboard_events(b"abc\n"));
+        runtime.push_frame(vec![simulated::event(VREvent_OverlayHidden)]);
+        runtime.push_frame(vec![simulated::event(VREvent_OverlayShown)]);
+        runtime.push_frame(vec![simulated::event(VREvent_FocusLeave)]);
+        runtime.push_system_frame(simulated::event(VREvent_Quit));
+        runtime
+    }
+
+    #[test]
+    fn simulated_session_sets_up_the_overlay_and_clicks_the_volume_slider() {
+        let _sdl = offscreen::lock_sdl();
+        offscreen::prefer_offscreen_driver();
+
+        let config = Config::default();
+        let mut backend = simulated_players();
+        let mut runtime = simulated_session(&config);
+
+        assert!(run_overlay(&mut runtime, &mut backend, &config, None) == Exit::RuntimeQuit);
+
+        assert_eq!(runtime.frame_count(), 9);
+        assert_eq!(runtime.idle_frame_count(), 1);
+
+        /* The thumbnail depends on the icon theme */
+        let (textures, changes): (Vec<_>, Vec<_>) = runtime.changes().iter().
+            filter(|change| !matches!(change, OverlayChange::Thumbnail(_))).
+            partition(|change| matches!(change, OverlayChange::Texture(_)));
+
+        assert_eq!(changes, [
+            &OverlayChange::InputMethodMouse,
+            &OverlayChange::Flag(VROverlayFlags_EnableControlBar() as u32, true),
+            &OverlayChange::Flag(VROverlayFlags_EnableControlBarClose() as u32, true),
+            &OverlayChange::Flag(VROverlayFlags_EnableControlBarKeyboard() as u32, true),
+            &OverlayChange::Flag(VROverlayFlags_SendVRSmoothScrollEvents as u32, true),
+            &OverlayChange::MouseScale([2048.0, 768.0]),
+            &OverlayChange::WidthInMeters(2.0),
+            &OverlayChange::QuitAcknowledged,
+        ]);
+        assert!(!textures.is_empty());
+
+        let commands = backend.take_commands();
+        assert_eq!(commands.len(), 1, "{:?}", commands);
+
+        let (bus_name, command) = &commands[0];
+        assert_eq!(bus_name, "org.mpris.MediaPlayer2.first");
+        let PlayerCommand::SetVolume(volume) = *command else { panic!("{:?} is not SetVolume", command) };
+        assert!(volume > 0.5 && volume < 1.0, "{}", volume);
+    }
+
+    #[test]
+    fn keyboard_follows_text_field_focus() {
+        let ctx = egui::Context::default();
+        let mut runtime = SimulatedRuntime::new();
+        let mut shown_for = None;
+        let mut text = String::new();
+        let id = egui::Id::new("field");
+
+        let mut frame = |focus: Option<bool>, runtime: &mut SimulatedRuntime| {
+            let output = ctx.run(RawInput::default(), |ctx| {
+                match focus {
+                    Some(true) => ctx.memory_mut(|memory| memory.request_focus(id)),
+                    Some(false) => ctx.memory_mut(|memory| memory.surrender_focus(id)),
+                    None => (),
+                }
+
+                egui::CentralPanel::default().show(ctx, |ui| {
+                    ui.add(egui::TextEdit::singleline(&mut text).id(id));
+                });
+            });
+            update_keyboard(runtime, &mut shown_for, ui::focused_text_field(&ctx, &output.platform_output));
+            runtime.changes().to_vec()
+        };
+
+        assert_eq!(frame(None, &mut runtime), []);
+        assert_eq!(frame(Some(true), &mut runtime), [OverlayChange::KeyboardShown]);
+        /* Only once while the same field keeps focus */
+        assert_eq!(frame(None, &mut runtime), [OverlayChange::KeyboardShown]);
+        assert_eq!(frame(Some(false), &mut runtime), [OverlayChange::KeyboardShown, OverlayChange::KeyboardHidden]);
+        assert_eq!(frame(None, &mut runtime), [OverlayChange::KeyboardShown, OverlayChange::KeyboardHidden]);
+    }
+
+    #[test]
+    fn overlay_exits_when_steamvr_goes_away_without_quitting() {
+        let _sdl = offscreen::lock_sdl();
+        offscreen::prefer_offscreen_driver();
+
+        let config = Config::default();
+        let mut backend = simulated_players();
+        let mut runtime = SimulatedRuntime::new();
+        runtime.push_frame(vec![]);
+        runtime.push_vanish_frame();
+
+        assert!(run_overlay(&mut runtime, &mut backend, &config, None) == Exit::RuntimeQuit);
+        /* Rather than at the VREvent_Quit that ends the script */
+        assert!(!runtime.changes().contains(&OverlayChange::QuitAcknowledged));
+    }
 }
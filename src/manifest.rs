@@ -0,0 +1,162 @@
+use serde::Serialize;
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::applications::APP_KEY;
+
+/// Name and description shown by SteamVR, by SteamVR locale.
+const STRINGS: &[(&str, Strings)] = &[
+    ("en_us", Strings {
+        name: "MPRIS Media Controller",
+        description: "Control and view the state of media players that support the MPRIS protocol from the OpenVR dashboard.",
+    }),
+    ("de_de", Strings {
+        name: "MPRIS-Mediensteuerung",
+        description: "Steuert Mediaplayer, die das MPRIS-Protokoll unterstützen, und zeigt ihren Zustand im OpenVR-Dashboard an.",
+    }),
+    ("es_es", Strings {
+        name: "Controlador multimedia MPRIS",
+        description: "Controla y consulta el estado de los reproductores multimedia compatibles con el protocolo MPRIS desde el panel de OpenVR.",
+    }),
+    ("fr_fr", Strings {
+        name: "Contrôleur multimédia MPRIS",
+        description: "Contrôlez et suivez l'état des lecteurs multimédias compatibles avec le protocole MPRIS depuis le tableau de bord OpenVR.",
+    }),
+    ("it_it", Strings {
+        name: "Controller multimediale MPRIS",
+        description: "Controlla e visualizza lo stato dei lettori multimediali che supportano il protocollo MPRIS dalla dashboard di OpenVR.",
+    }),
+    ("pt_br", Strings {
+        name: "Controlador de mídia MPRIS",
+        description: "Controle e veja o estado dos reprodutores de mídia compatíveis com o protocolo MPRIS a partir do painel do OpenVR.",
+    }),
+];
+
+/// The `.vrmanifest` through which SteamVR lists the overlay among its
+/// applications and starts it.
+#[derive(Serialize)]
+pub struct Manifest {
+    source: &'static str,
+    applications: Vec<Application>,
+}
+
+#[derive(Serialize)]
+struct Application {
+    app_key: &'static str,
+    launch_type: &'static str,
+    binary_path_linux: String,
+    arguments: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image_path: Option<String>,
+    is_dashboard_overlay: bool,
+    strings: BTreeMap<&'static str, Strings>,
+}
+
+#[derive(Clone, Copy, Serialize)]
+struct Strings {
+    name: &'static str,
+    description: &'static str,
+}
+
+/// Quotes `arg` for the command line SteamVR builds from `arguments`, or
+/// systemd and D-Bus from their `Exec` lines, when it has to be.
+pub fn quote_argument(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\') {
+        return arg.to_string();
+    }
+
+    let mut quoted = String::from("\"");
+    for c in arg.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+impl Manifest {
+    /// Describes the overlay run from `binary_path` with the configuration
+    /// at `config_path`, or the default one. Paths have to be absolute since
+    /// SteamVR does not start the overlay from any particular directory.
+    pub fn new(binary_path: &Path, image_path: Option<&Path>, config_path: Option<&Path>) -> Manifest {
+        let mut arguments = vec!["run".to_string()];
+        if let Some(config_path) = config_path {
+            arguments.push("--config".to_string());
+            arguments.push(quote_argument(&config_path.to_string_lossy()));
+        }
+
+        Manifest {
+            source: "builtin",
+            applications: vec![Application {
+                app_key: APP_KEY,
+                launch_type: "binary",
+                binary_path_linux: binary_path.to_string_lossy().into_owned(),
+                arguments: arguments.join(" "),
+                image_path: image_path.map(|path| path.to_string_lossy().into_owned()),
+                is_dashboard_overlay: true,
+                strings: STRINGS.iter().copied().collect(),
+            }],
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Failed to serialize the manifest")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest_json(config_path: Option<&Path>) -> serde_json::Value {
+        let manifest = Manifest::new(
+            Path::new("/usr/bin/mpris-openvr-overlay"),
+            Some(Path::new("/usr/share/icons/overlay.png")),
+            config_path);
+
+        serde_json::from_str(&manifest.to_json()).expect("Failed to parse the manifest")
+    }
+
+    #[test]
+    fn application_describes_the_overlay() {
+        let json = manifest_json(None);
+        let application = &json["applications"][0];
+
+        assert_eq!(json["source"], "builtin");
+        assert_eq!(application["app_key"], APP_KEY);
+        assert_eq!(application["launch_type"], "binary");
+        assert_eq!(application["binary_path_linux"], "/usr/bin/mpris-openvr-overlay");
+        assert_eq!(application["image_path"], "/usr/share/icons/overlay.png");
+        assert_eq!(application["is_dashboard_overlay"], true);
+        assert_eq!(application["arguments"], "run");
+    }
+
+    #[test]
+    fn config_path_is_quoted_in_arguments() {
+        let json = manifest_json(Some(Path::new("/home/user/my \"media\" overlay.toml")));
+        assert_eq!(json["applications"][0]["arguments"],
+                   r#"run --config "/home/user/my \"media\" overlay.toml""#);
+
+        let json = manifest_json(Some(Path::new("/home/user/config.toml")));
+        assert_eq!(json["applications"][0]["arguments"], "run --config /home/user/config.toml");
+    }
+
+    #[test]
+    fn every_locale_has_a_name_and_description() {
+        let json = manifest_json(None);
+        let strings = json["applications"][0]["strings"].as_object().expect("strings is not an object");
+
+        assert_eq!(strings.len(), STRINGS.len());
+        assert!(strings.contains_key("en_us"));
+
+        for (locale, strings) in strings {
+            for key in ["name", "description"] {
+                let value = strings[key].as_str().unwrap_or_default();
+                assert!(!value.is_empty(), "{} has no {}", locale, key);
+            }
+        }
+    }
+}
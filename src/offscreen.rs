@@ -0,0 +1,142 @@
+use egui_sdl2_gl::{
+    egui, gl, sdl2, ShaderVersion,
+    painter::Painter
+};
+
+use egui::{
+    RawInput, FullOutput
+};
+
+use std::time::Duration;
+
+use crate::{RenderTarget, open_gl_window};
+use crate::config::OverlayConfig;
+
+/// Frames to wait at most for images and other pending content to settle.
+const MAX_SETTLE_FRAMES: usize = 100;
+const SETTLE_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Makes SDL draw without a display unless `SDL_VIDEODRIVER` says otherwise.
+pub fn prefer_offscreen_driver() {
+    if std::env::var_os("SDL_VIDEODRIVER").is_none() {
+        sdl2::hint::set("SDL_VIDEODRIVER", "offscreen");
+    }
+}
+
+/// SDL only supports one event pump at a time, so tests that open windows
+/// take turns.
+#[cfg(test)]
+pub fn lock_sdl() -> std::sync::MutexGuard<'static, ()> {
+    static SDL: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    SDL.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Draws the UI into an overlay-sized texture without SteamVR or a visible
+/// window, and reads the result back.
+///
+/// SDL uses its offscreen video driver unless `SDL_VIDEODRIVER` says
+/// otherwise, so this also works with software rendering on a machine without
+/// a display.
+pub struct OffscreenRenderer {
+    /* Fields are dropped in order, and the GL objects need the context */
+    painter: Painter,
+    target: RenderTarget,
+    egui_ctxt: egui::Context,
+    width: usize,
+    height: usize,
+    _context: sdl2::video::GLContext,
+    _window: sdl2::video::Window,
+    _sdl: sdl2::Sdl,
+}
+
+impl OffscreenRenderer {
+    /// Renders at the texture size and UI scale of `config`.
+    pub fn new(config: &OverlayConfig) -> OffscreenRenderer {
+        let (width, height) = (config.width, config.height);
+
+        prefer_offscreen_driver();
+
+        let sdl = sdl2::init().expect("Failed to initialize SDL");
+
+        let sdl_video = sdl.video().expect("Failed to initialize SDL Video");
+        let window = open_gl_window(&sdl_video, sdl_video.window("mpris-openvr-overlay", 128, 128).hidden());
+        let context = window.gl_create_context().expect("Failed to create OpenGL context");
+
+        gl::load_with(|s| sdl_video.gl_get_proc_address(s) as *const _);
+
+        let target = RenderTarget::new(width, height);
+
+        unsafe {
+            gl::Disable(gl::DEPTH_TEST);
+        }
+
+        let egui_ctxt = egui::Context::default();
+        egui_extras::install_image_loaders(&egui_ctxt);
+
+        /* Not through egui_sdl2_gl::with_sdl2, which needs system cursors that
+         * SDL's offscreen driver does not have */
+        let mut painter = Painter::new(&window, config.ui_scale as f32, ShaderVersion::Default);
+        painter.update_screen_rect((width as u32, height as u32));
+
+        OffscreenRenderer {
+            painter, target, egui_ctxt, width, height,
+            _context: context, _window: window, _sdl: sdl
+        }
+    }
+
+    /// The context the UI is run in, to set it up before rendering.
+    pub fn context(&self) -> &egui::Context {
+        &self.egui_ctxt
+    }
+
+    /// Runs `show` until the UI stops asking for repaints (e.g. once the
+    /// images it shows are loaded), then paints the last frame.
+    pub fn render(&mut self, mut show: impl FnMut(&egui::Context)) -> image::RgbaImage {
+        let mut output = FullOutput::default();
+
+        for frame in 0..MAX_SETTLE_FRAMES {
+            let input = RawInput {
+                screen_rect: Some(self.painter.screen_rect),
+                pixels_per_point: Some(self.painter.pixels_per_point),
+                time: Some(frame as f64 * SETTLE_INTERVAL.as_secs_f64()),
+                ..Default::default()
+            };
+
+            output.append(self.egui_ctxt.run(input, &mut show));
+
+            if !output.repaint_after.is_zero() {
+                break;
+            }
+
+            std::thread::sleep(SETTLE_INTERVAL);
+        }
+
+        let paint_jobs = self.egui_ctxt.tessellate(output.shapes);
+
+        unsafe {
+            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, self.target.fbo);
+            gl::Viewport(0, 0, self.width as i32, self.height as i32);
+            gl::DrawBuffer(gl::COLOR_ATTACHMENT0);
+
+            gl::ClearColor(0.0, 0.0, 0.0, 0.0);
+            gl::Clear(gl::COLOR_BUFFER_BIT);
+        }
+
+        self.painter.paint_jobs(None, output.textures_delta, paint_jobs);
+
+        let mut pixels = vec![0u8; self.width * self.height * 4];
+        unsafe {
+            gl::Finish();
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.target.fbo);
+            gl::ReadBuffer(gl::COLOR_ATTACHMENT0);
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+            gl::ReadPixels(0, 0, self.width as i32, self.height as i32, gl::RGBA, gl::UNSIGNED_BYTE,
+                           pixels.as_mut_ptr() as *mut _);
+        }
+
+        /* OpenGL rows start at the bottom */
+        let image = image::RgbaImage::from_raw(self.width as u32, self.height as u32, pixels).
+            expect("Pixel buffer does not match the texture size");
+        image::imageops::flip_vertical(&image)
+    }
+}
@@ -0,0 +1,853 @@
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::time::{Duration, Instant};
+
+use dbus::arg::Variant;
+use dbus::ffidisp::{BusType, Connection};
+use dbus::Message;
+
+use mpris::{LoopStatus, Metadata, MetadataValue, PlaybackStatus};
+
+use crate::backend::{MediaBackend, PlayerCommand, PlayerState};
+use crate::config::PlayersConfig;
+
+const MPRIS2_PREFIX: &str = "org.mpris.MediaPlayer2.";
+const MPRIS2_PATH: &str = "/org/mpris/MediaPlayer2";
+const ROOT_INTERFACE: &str = "org.mpris.MediaPlayer2";
+const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+const PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+
+/// Values of D-Bus properties by name.
+type Properties = HashMap<String, MetadataValue>;
+
+/// Reads single properties of a player, which is all keeping a tracked
+/// player up to date needs from the bus.
+trait PropertySource {
+    fn get(&self, bus_name: &str, interface: &str, name: &str) -> Result<MetadataValue, dbus::Error>;
+}
+
+/// Wait before trying to reach the session bus again, doubled after every
+/// failed attempt up to the maximum.
+const MIN_RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Handle to the thread that owns all D-Bus communication with media players.
+///
+/// Calls to a player can block for as long as the D-Bus timeout, so they never
+/// happen on the render loop: the worker publishes snapshots of every player's
+/// state whenever it changes and applies commands sent to it. Without a
+/// session bus, it keeps trying to connect.
+pub struct PlayerWorker {
+    requests: Sender<Request>,
+    /// Written to after each request, to wake the worker from waiting on the
+    /// bus.
+    wake: UnixStream,
+    updates: Receiver<Update>,
+    connection_error: Option<String>,
+}
+
+enum Request {
+    Command(String, PlayerCommand),
+    Reconfigure(PlayersConfig),
+}
+
+enum Update {
+    Players(Vec<PlayerState>),
+    Disconnected(String),
+}
+
+impl PlayerWorker {
+    pub fn spawn(config: &PlayersConfig) -> PlayerWorker {
+        let config = config.clone();
+        let (request_tx, request_rx) = mpsc::channel();
+        let (update_tx, update_rx) = mpsc::channel();
+        let (wake_tx, wake_rx) = UnixStream::pair().expect("Failed to create player worker wake-up socket");
+
+        /* A full buffer already wakes the worker, and it must never block the UI */
+        for wake in [&wake_tx, &wake_rx] {
+            wake.set_nonblocking(true).expect("Failed to create player worker wake-up socket");
+        }
+
+        std::thread::Builder::new().
+            name("player-worker".to_string()).
+            spawn(move || run_worker(config, request_rx, wake_rx, update_tx)).
+            expect("Failed to spawn player worker");
+
+        PlayerWorker { requests: request_tx, wake: wake_tx, updates: update_rx, connection_error: None }
+    }
+
+    fn request(&self, request: Request) {
+        if self.requests.send(request).is_ok() {
+            let _ = (&self.wake).write(&[0]);
+        }
+    }
+}
+
+impl MediaBackend for PlayerWorker {
+    fn poll_players(&mut self) -> Option<Vec<PlayerState>> {
+        let mut players = None;
+
+        for update in self.updates.try_iter() {
+            match update {
+                Update::Players(new_players) => {
+                    self.connection_error = None;
+                    players = Some(new_players);
+                },
+                Update::Disconnected(err) => {
+                    self.connection_error = Some(err);
+                    players = Some(vec![]);
+                },
+            }
+        }
+
+        players
+    }
+
+    fn connection_error(&self) -> Option<String> {
+        self.connection_error.clone()
+    }
+
+    fn send(&mut self, bus_name: &str, command: PlayerCommand) {
+        self.request(Request::Command(bus_name.to_string(), command));
+    }
+
+    fn reconfigure(&mut self, config: &PlayersConfig) {
+        self.request(Request::Reconfigure(config.clone()));
+    }
+}
+
+/// Sets the part of `state` that the Player interface property `name` holds.
+/// A player that sends an optional property supports it.
+fn apply_property(state: &mut PlayerState, name: &str, value: MetadataValue) {
+    match name {
+        "Metadata" => { state.metadata = value.into_map().map(Metadata::from).unwrap_or_default(); },
+        "PlaybackStatus" => {
+            if let Some(status) = value.as_str().and_then(|status| status.parse().ok()) {
+                state.playback_status = status;
+            }
+        },
+        "Rate" => { state.rate = value.as_f64().unwrap_or(1.0); },
+        "Volume" => { state.volume = value.as_f64(); },
+        "Position" => {
+            state.position = value.as_i64().map(from_micros);
+            state.position_updated = Instant::now();
+        },
+        "Shuffle" => { state.shuffle = value.as_bool(); },
+        "LoopStatus" => { state.loop_status = value.as_str().and_then(|status| status.parse().ok()); },
+        "CanGoPrevious" => { state.can_go_previous = value.as_bool().unwrap_or(false); },
+        "CanGoNext" => { state.can_go_next = value.as_bool().unwrap_or(false); },
+        "CanControl" => { state.can_stop = value.as_bool().unwrap_or(false); },
+        _ => ()
+    }
+}
+
+fn from_micros(micros: i64) -> Duration {
+    Duration::from_micros(micros.max(0) as u64)
+}
+
+/// A player's state, read once and then kept up to date from the signals it
+/// sends, so that only properties a player invalidates without sending their
+/// new value have to be asked for again.
+struct TrackedPlayer {
+    /// Unique name of the connection that owns the bus name, which is where
+    /// signals come from.
+    owner: String,
+    state: PlayerState,
+}
+
+impl TrackedPlayer {
+    fn new(watcher: &BusWatcher, bus_name: &str) -> Option<TrackedPlayer> {
+        let owner = watcher.name_owner(bus_name).ok()?;
+        let identity = watcher.get(bus_name, ROOT_INTERFACE, "Identity").ok()?.into_string()?;
+        let properties = watcher.get_all(bus_name, PLAYER_INTERFACE).ok()?;
+
+        Some(TrackedPlayer::from_properties(owner, bus_name, identity, properties))
+    }
+
+    /// A player with the Player interface `properties` it reported. Those it
+    /// left out are unsupported.
+    fn from_properties(owner: String, bus_name: &str, identity: String, properties: Properties) -> TrackedPlayer {
+        let mut state = PlayerState {
+            bus_name: bus_name.to_string(),
+            identity,
+            metadata: Metadata::default(),
+            volume: None,
+            position: None,
+            position_updated: Instant::now(),
+            rate: 1.0,
+            playback_status: PlaybackStatus::Stopped,
+            shuffle: None,
+            loop_status: None,
+            can_go_previous: false,
+            can_go_next: false,
+            can_stop: false,
+        };
+
+        for (name, value) in properties {
+            apply_property(&mut state, &name, value);
+        }
+
+        TrackedPlayer { owner, state }
+    }
+
+    /// Applies a PropertiesChanged signal, returning true if the state of the
+    /// player changed.
+    fn properties_changed(&mut self, watcher: &dyn PropertySource, interface: &str,
+                          mut properties: Properties, invalidated: Vec<String>) -> bool {
+        if interface == ROOT_INTERFACE {
+            return match properties.remove("Identity").and_then(MetadataValue::into_string) {
+                Some(identity) => {
+                    self.state.identity = identity;
+                    true
+                },
+                None => false,
+            };
+        }
+        else if interface != PLAYER_INTERFACE {
+            return false;
+        }
+
+        for name in invalidated {
+            if let Ok(value) = watcher.get(&self.state.bus_name, PLAYER_INTERFACE, &name) {
+                properties.insert(name, value);
+            }
+        }
+
+        /* Players do not signal the position moving, only the reasons it might not have moved as expected */
+        let position_moved = ["Metadata", "PlaybackStatus", "Rate"].iter().
+            any(|&name| properties.contains_key(name));
+        if self.state.position.is_some() && position_moved && !properties.contains_key("Position") {
+            if let Ok(position) = watcher.get(&self.state.bus_name, PLAYER_INTERFACE, "Position") {
+                properties.insert("Position".to_string(), position);
+            }
+        }
+
+        let changed = !properties.is_empty();
+        for (name, value) in properties {
+            apply_property(&mut self.state, &name, value);
+        }
+
+        changed
+    }
+
+    fn seeked(&mut self, position: Duration) {
+        self.state.position = Some(position);
+        self.state.position_updated = Instant::now();
+    }
+}
+
+enum BusEvent {
+    PlayerAppeared(String),
+    PlayerVanished(String),
+    PropertiesChanged {
+        sender: String,
+        interface: String,
+        changed: Properties,
+        invalidated: Vec<String>,
+    },
+    Seeked {
+        sender: String,
+        position: Duration,
+    },
+    /// The connection to the bus is gone, and the players with it.
+    Disconnected(String),
+}
+
+impl BusEvent {
+    fn from_message(message: &Message) -> Option<BusEvent> {
+        match &*message.member()? {
+            "NameOwnerChanged" => {
+                let (name, _old_owner, new_owner) = message.get3::<String, String, String>();
+                let name = name.filter(|name| name.starts_with(MPRIS2_PREFIX))?;
+
+                Some(if new_owner?.is_empty() {
+                    BusEvent::PlayerVanished(name)
+                } else {
+                    BusEvent::PlayerAppeared(name)
+                })
+            },
+            "PropertiesChanged" => {
+                let (interface, changed, invalidated) = message.read3().ok()?;
+                Some(BusEvent::PropertiesChanged {
+                    sender: message.sender()?.to_string(),
+                    interface, changed, invalidated
+                })
+            },
+            "Seeked" => {
+                Some(BusEvent::Seeked {
+                    sender: message.sender()?.to_string(),
+                    position: from_micros(message.read1().ok()?),
+                })
+            },
+            _ => None
+        }
+    }
+}
+
+/// The worker's only connection to the bus. It watches players appear and
+/// vanish, receives their signals, reads their properties and sends them
+/// commands.
+struct BusWatcher {
+    connection: Connection,
+    config: PlayersConfig,
+}
+
+impl BusWatcher {
+    fn new(config: PlayersConfig) -> Result<BusWatcher, dbus::Error> {
+        let connection = Connection::get_private(BusType::Session)?;
+        connection.add_match(
+            "type='signal',sender='org.freedesktop.DBus',interface='org.freedesktop.DBus',\
+             member='NameOwnerChanged',arg0namespace='org.mpris.MediaPlayer2'")?;
+        connection.add_match(
+            "type='signal',interface='org.freedesktop.DBus.Properties',member='PropertiesChanged',\
+             path='/org/mpris/MediaPlayer2'")?;
+        connection.add_match(
+            "type='signal',interface='org.mpris.MediaPlayer2.Player',member='Seeked',\
+             path='/org/mpris/MediaPlayer2'")?;
+
+        Ok(BusWatcher { connection, config })
+    }
+
+    fn call(&self, message: Message) -> Result<Message, dbus::Error> {
+        self.connection.send_with_reply_and_block(message, self.config.dbus_timeout_ms)
+    }
+
+    fn player_names(&self) -> Result<Vec<String>, dbus::Error> {
+        let list_names = Message::new_method_call(
+            "org.freedesktop.DBus", "/", "org.freedesktop.DBus", "ListNames").unwrap();
+
+        let reply = self.call(list_names)?;
+
+        let mut names: Vec<String> = reply.get1::<Vec<String>>().unwrap_or_default().
+            into_iter().filter(|name| name.starts_with(MPRIS2_PREFIX)).collect();
+        names.sort_by_key(|name| name.to_lowercase());
+        Ok(names)
+    }
+
+    fn name_owner(&self, bus_name: &str) -> Result<String, dbus::Error> {
+        let get_name_owner = Message::new_method_call(
+            "org.freedesktop.DBus", "/", "org.freedesktop.DBus", "GetNameOwner").unwrap().
+            append1(bus_name);
+
+        Ok(self.call(get_name_owner)?.read1()?)
+    }
+
+    fn get_all(&self, bus_name: &str, interface: &str) -> Result<Properties, dbus::Error> {
+        let get_all = Message::new_method_call(bus_name, MPRIS2_PATH, PROPERTIES_INTERFACE, "GetAll").
+            map_err(|err| dbus::Error::new_failed(&err))?.
+            append1(interface);
+
+        Ok(self.call(get_all)?.read1()?)
+    }
+
+    /// Returns the events that are already queued or can be read without
+    /// waiting.
+    fn poll(&self) -> Vec<BusEvent> {
+        if !self.connection.is_connected() {
+            return vec![BusEvent::Disconnected("Lost the connection to the session bus".to_string())];
+        }
+
+        self.connection.incoming(0).filter_map(|message| BusEvent::from_message(&message)).collect()
+    }
+
+    /// Blocks until the bus has something to read, or until `wake` is written
+    /// to, then empties `wake`.
+    fn wait(&self, wake: &UnixStream) {
+        let mut fds: Vec<_> = self.connection.watch_fds().iter().map(|watch| watch.to_pollfd()).collect();
+        fds.push(libc::pollfd { fd: wake.as_raw_fd(), events: libc::POLLIN, revents: 0 });
+
+        /* An interrupted wait is just a spurious wake-up */
+        unsafe {
+            libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1);
+        }
+
+        let mut buf = [0; 64];
+        while matches!((&*wake).read(&mut buf), Ok(n) if n > 0) {}
+    }
+}
+
+impl PropertySource for BusWatcher {
+    fn get(&self, bus_name: &str, interface: &str, name: &str) -> Result<MetadataValue, dbus::Error> {
+        let get = Message::new_method_call(bus_name, MPRIS2_PATH, PROPERTIES_INTERFACE, "Get").
+            map_err(|err| dbus::Error::new_failed(&err))?.
+            append2(interface, name);
+
+        Ok(self.call(get)?.read1()?)
+    }
+}
+
+/// The players on the session bus right now, read once rather than tracked.
+pub fn list_players(config: &PlayersConfig) -> Result<Vec<PlayerState>, dbus::Error> {
+    let watcher = BusWatcher::new(config.clone())?;
+
+    Ok(watcher.player_names()?.iter().
+        filter_map(|name| Some(TrackedPlayer::new(&watcher, name)?.state)).
+        collect())
+}
+
+fn loop_status_name(status: LoopStatus) -> &'static str {
+    match status {
+        LoopStatus::None => "None",
+        LoopStatus::Track => "Track",
+        LoopStatus::Playlist => "Playlist",
+    }
+}
+
+/// Sends `command` without waiting for the player to carry it out, so that a
+/// player that hangs does not hold up the others.
+fn apply_command(watcher: &BusWatcher, bus_name: &str, command: PlayerCommand) {
+    let call = |method: &str| Message::new_method_call(bus_name, MPRIS2_PATH, PLAYER_INTERFACE, method);
+    let set = |name: &str| {
+        Message::new_method_call(bus_name, MPRIS2_PATH, PROPERTIES_INTERFACE, "Set").
+            map(|message| message.append2(PLAYER_INTERFACE, name))
+    };
+
+    let message = match command {
+        PlayerCommand::Play => call("Play"),
+        PlayerCommand::Pause => call("Pause"),
+        PlayerCommand::Stop => call("Stop"),
+        PlayerCommand::Previous => call("Previous"),
+        PlayerCommand::Next => call("Next"),
+        PlayerCommand::SetVolume(volume) => set("Volume").map(|message| message.append1(Variant(volume.max(0.0)))),
+        PlayerCommand::SetPosition(id, position) => {
+            call("SetPosition").map(|message| message.append2(dbus::Path::from(&id), position.as_micros() as i64))
+        },
+        PlayerCommand::SetShuffle(state) => set("Shuffle").map(|message| message.append1(Variant(state))),
+        PlayerCommand::SetLoopStatus(status) => {
+            set("LoopStatus").map(|message| message.append1(Variant(loop_status_name(status))))
+        },
+    };
+
+    if let Ok(mut message) = message {
+        message.set_no_reply(true);
+        let _ = watcher.connection.send(message);
+    }
+}
+
+/// Replaces whatever was tracked under `name`, which may have had another
+/// owner, by `player`, keeping the players sorted.
+fn player_appeared(tracked: &mut Vec<TrackedPlayer>, name: &str, player: Option<TrackedPlayer>) {
+    tracked.retain(|p| p.state.bus_name != name);
+    tracked.extend(player);
+    tracked.sort_by_key(|p| p.state.bus_name.to_lowercase());
+}
+
+/// Returns whether `name` was tracked.
+fn player_vanished(tracked: &mut Vec<TrackedPlayer>, name: &str) -> bool {
+    let count = tracked.len();
+    tracked.retain(|p| p.state.bus_name != name);
+    tracked.len() != count
+}
+
+fn find_player<'a>(tracked: &'a mut [TrackedPlayer], owner: &str) -> Option<&'a mut TrackedPlayer> {
+    tracked.iter_mut().find(|p| p.owner == owner)
+}
+
+/// Tracks the players on `watcher`'s bus until the UI goes away, returning
+/// None, or the connection is lost, returning why. Players are read once when
+/// they appear, and only the one a signal is about changes.
+fn run_connected(watcher: &mut BusWatcher, requests: &Receiver<Request>, wake: &UnixStream,
+                 updates: &Sender<Update>) -> Option<String> {
+    let names = match watcher.player_names() {
+        Ok(names) => names,
+        Err(err) => return Some(err.to_string()),
+    };
+
+    let mut tracked: Vec<_> = names.iter().
+        filter_map(|name| TrackedPlayer::new(watcher, name)).collect();
+    let mut changed = true;
+    let mut idle = false;
+
+    loop {
+        if changed {
+            updates.send(Update::Players(tracked.iter().map(|p| p.state.clone()).collect())).ok()?;
+            changed = false;
+        }
+
+        /* Every player's signals arrive on this connection, so one wait covers
+         * them all. Handling events makes calls, which can queue more. */
+        if idle {
+            watcher.wait(wake);
+        }
+
+        let events = watcher.poll();
+        idle = events.is_empty();
+
+        for event in events {
+            match event {
+                BusEvent::PlayerAppeared(name) => {
+                    player_appeared(&mut tracked, &name, TrackedPlayer::new(watcher, &name));
+                    changed = true;
+                },
+                BusEvent::PlayerVanished(name) => {
+                    changed |= player_vanished(&mut tracked, &name);
+                },
+                BusEvent::PropertiesChanged { sender, interface, changed: properties, invalidated } => {
+                    if let Some(p) = find_player(&mut tracked, &sender) {
+                        changed |= p.properties_changed(watcher, &interface, properties, invalidated);
+                    }
+                },
+                BusEvent::Seeked { sender, position } => {
+                    if let Some(p) = find_player(&mut tracked, &sender) {
+                        p.seeked(position);
+                        changed = true;
+                    }
+                },
+                BusEvent::Disconnected(err) => return Some(err),
+            }
+        }
+
+        loop {
+            match requests.try_recv() {
+                Ok(Request::Command(bus_name, command)) => {
+                    if tracked.iter().any(|p| p.state.bus_name == bus_name) {
+                        apply_command(watcher, &bus_name, command);
+                    }
+                },
+                /* The timeout is read again on every call */
+                Ok(Request::Reconfigure(config)) => { watcher.config = config; },
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return None,
+            }
+        }
+    }
+}
+
+/// Waits for `interval` before connecting again, still following changes to
+/// the configuration. Commands are dropped since there are no players to
+/// send them to. Returns false if the UI went away.
+fn wait_to_reconnect(interval: Duration, config: &mut PlayersConfig, requests: &Receiver<Request>) -> bool {
+    let deadline = Instant::now() + interval;
+
+    loop {
+        match requests.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(Request::Reconfigure(new_config)) => { *config = new_config; },
+            Ok(Request::Command(..)) => (),
+            Err(RecvTimeoutError::Timeout) => return true,
+            Err(RecvTimeoutError::Disconnected) => return false,
+        }
+    }
+}
+
+fn run_worker(mut config: PlayersConfig, requests: Receiver<Request>, wake: UnixStream, updates: Sender<Update>) {
+    let mut interval = MIN_RECONNECT_INTERVAL;
+    let mut last_error = None;
+
+    loop {
+        let err = match BusWatcher::new(config.clone()) {
+            Ok(mut watcher) => {
+                log::info!("Connected to the session bus");
+                interval = MIN_RECONNECT_INTERVAL;
+                last_error = None;
+
+                let err = run_connected(&mut watcher, &requests, &wake, &updates);
+                config = watcher.config;
+
+                match err {
+                    Some(err) => err,
+                    None => return,
+                }
+            },
+            Err(err) => err.to_string(),
+        };
+
+        /* Only once while the bus stays away the same way */
+        if last_error.as_ref() != Some(&err) {
+            log::warn!("Not connected to D-Bus: {}", err);
+        }
+
+        if updates.send(Update::Disconnected(err.clone())).is_err() {
+            return;
+        }
+        last_error = Some(err);
+
+        if !wait_to_reconnect(interval, &mut config, &requests) {
+            return;
+        }
+        interval = (interval * 2).min(MAX_RECONNECT_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use dbus::arg::{PropMap, RefArg};
+    use std::cell::RefCell;
+
+    const SENDER: &str = ":1.42";
+
+    /// Answers `Get` from a fixed set of properties, remembering what was
+    /// asked for.
+    #[derive(Default)]
+    struct FakeSource {
+        values: Properties,
+        asked: RefCell<Vec<String>>,
+    }
+
+    impl PropertySource for FakeSource {
+        fn get(&self, _bus_name: &str, _interface: &str, name: &str) -> Result<MetadataValue, dbus::Error> {
+            self.asked.borrow_mut().push(name.to_string());
+            self.values.get(name).cloned().ok_or_else(|| dbus::Error::new_failed("No such property"))
+        }
+    }
+
+    fn source(values: &[(&str, MetadataValue)]) -> FakeSource {
+        FakeSource {
+            values: values.iter().map(|(name, value)| (name.to_string(), value.clone())).collect(),
+            ..FakeSource::default()
+        }
+    }
+
+    fn variant(value: impl RefArg + 'static) -> Variant<Box<dyn RefArg>> {
+        Variant(Box::new(value))
+    }
+
+    fn signal(path: &str, interface: &str, member: &str) -> Message {
+        let mut message = Message::signal(&path.into(), &interface.into(), &member.into());
+        message.set_sender(Some(SENDER.into()));
+        message
+    }
+
+    fn properties_changed_signal(interface: &str, changed: PropMap, invalidated: &[&str]) -> Message {
+        let invalidated: Vec<String> = invalidated.iter().map(|name| name.to_string()).collect();
+        signal(MPRIS2_PATH, PROPERTIES_INTERFACE, "PropertiesChanged").append3(interface, changed, invalidated)
+    }
+
+    /// A player that reported `properties` when it appeared.
+    fn player(properties: &[(&str, MetadataValue)]) -> TrackedPlayer {
+        let properties = properties.iter().map(|(name, value)| (name.to_string(), value.clone())).collect();
+        TrackedPlayer::from_properties(SENDER.to_string(), "org.mpris.MediaPlayer2.test", "Test".to_string(), properties)
+    }
+
+    /// Decodes `message` and applies it to `player`, as the worker would.
+    fn receive(player: &mut TrackedPlayer, source: &FakeSource, message: Message) -> bool {
+        match BusEvent::from_message(&message) {
+            Some(BusEvent::PropertiesChanged { sender, interface, changed, invalidated }) => {
+                assert_eq!(sender, SENDER);
+                player.properties_changed(source, &interface, changed, invalidated)
+            },
+            Some(BusEvent::Seeked { sender, position }) => {
+                assert_eq!(sender, SENDER);
+                player.seeked(position);
+                true
+            },
+            _ => panic!("Not a player signal"),
+        }
+    }
+
+    #[test]
+    fn each_property_sets_its_field() {
+        let mut metadata = PropMap::new();
+        metadata.insert("xesam:title".to_string(), variant("Song".to_string()));
+        metadata.insert("mpris:length".to_string(), variant(300_000_000i64));
+
+        let mut changed = PropMap::new();
+        changed.insert("Metadata".to_string(), variant(metadata));
+        changed.insert("PlaybackStatus".to_string(), variant("Playing".to_string()));
+        changed.insert("Rate".to_string(), variant(2.0f64));
+        changed.insert("Volume".to_string(), variant(0.3f64));
+        changed.insert("Position".to_string(), variant(5_000_000i64));
+        changed.insert("Shuffle".to_string(), variant(true));
+        changed.insert("LoopStatus".to_string(), variant("Track".to_string()));
+        changed.insert("CanGoPrevious".to_string(), variant(true));
+        changed.insert("CanGoNext".to_string(), variant(true));
+        changed.insert("CanControl".to_string(), variant(true));
+
+        let mut player = player(&[]);
+        let source = FakeSource::default();
+        assert!(receive(&mut player, &source, properties_changed_signal(PLAYER_INTERFACE, changed, &[])));
+
+        let state = &player.state;
+        assert_eq!(state.metadata.title(), Some("Song"));
+        assert_eq!(state.metadata.length(), Some(Duration::from_secs(300)));
+        assert_eq!(state.playback_status, PlaybackStatus::Playing);
+        assert_eq!(state.rate, 2.0);
+        assert_eq!(state.volume, Some(0.3));
+        assert_eq!(state.position, Some(Duration::from_secs(5)));
+        assert_eq!(state.shuffle, Some(true));
+        assert_eq!(state.loop_status, Some(LoopStatus::Track));
+        assert!(state.can_go_previous);
+        assert!(state.can_go_next);
+        assert!(state.can_stop);
+
+        /* The signal carried the position along with the reasons to read it */
+        assert!(source.asked.borrow().is_empty());
+    }
+
+    #[test]
+    fn optional_properties_are_only_supported_once_sent() {
+        let mut player = player(&[("PlaybackStatus", MetadataValue::String("Paused".to_string()))]);
+
+        let state = &player.state;
+        assert_eq!(state.playback_status, PlaybackStatus::Paused);
+        assert_eq!((state.volume, state.position, state.shuffle, state.loop_status), (None, None, None, None));
+        assert!(!state.can_go_previous && !state.can_go_next && !state.can_stop);
+
+        let mut changed = PropMap::new();
+        changed.insert("Volume".to_string(), variant(0.5f64));
+        changed.insert("Shuffle".to_string(), variant("yes".to_string()));
+        receive(&mut player, &FakeSource::default(), properties_changed_signal(PLAYER_INTERFACE, changed, &[]));
+
+        /* A value of the wrong type does not count */
+        assert_eq!(player.state.volume, Some(0.5));
+        assert_eq!(player.state.shuffle, None);
+    }
+
+    #[test]
+    fn invalidated_properties_are_read_again() {
+        let mut player = player(&[
+            ("Volume", MetadataValue::F64(0.5)),
+            ("Shuffle", MetadataValue::Bool(false)),
+        ]);
+        let source = source(&[("Volume", MetadataValue::F64(0.8))]);
+
+        assert!(receive(&mut player, &source, properties_changed_signal(PLAYER_INTERFACE, PropMap::new(), &["Volume", "Shuffle"])));
+        assert_eq!(*source.asked.borrow(), ["Volume", "Shuffle"]);
+
+        /* One that cannot be read keeps its last value */
+        assert_eq!(player.state.volume, Some(0.8));
+        assert_eq!(player.state.shuffle, Some(false));
+
+        let source = FakeSource::default();
+        assert!(!receive(&mut player, &source, properties_changed_signal(PLAYER_INTERFACE, PropMap::new(), &["Shuffle"])));
+    }
+
+    #[test]
+    fn position_is_read_again_when_it_may_have_jumped() {
+        for name in ["Metadata", "PlaybackStatus", "Rate"] {
+            let mut changed = PropMap::new();
+            match name {
+                "Metadata" => { changed.insert(name.to_string(), variant(PropMap::new())); },
+                "PlaybackStatus" => { changed.insert(name.to_string(), variant("Playing".to_string())); },
+                _ => { changed.insert(name.to_string(), variant(1.5f64)); },
+            }
+
+            let mut player = player(&[("Position", MetadataValue::I64(1_000_000))]);
+            let source = source(&[("Position", MetadataValue::I64(7_000_000))]);
+
+            receive(&mut player, &source, properties_changed_signal(PLAYER_INTERFACE, changed, &[]));
+            assert_eq!(*source.asked.borrow(), ["Position"], "after {}", name);
+            assert_eq!(player.state.position, Some(Duration::from_secs(7)), "after {}", name);
+        }
+    }
+
+    #[test]
+    fn position_is_not_read_for_other_changes() {
+        let mut changed = PropMap::new();
+        changed.insert("Volume".to_string(), variant(0.2f64));
+        let mut player = player(&[("Position", MetadataValue::I64(1_000_000))]);
+        let source = source(&[("Position", MetadataValue::I64(7_000_000))]);
+        receive(&mut player, &source, properties_changed_signal(PLAYER_INTERFACE, changed, &[]));
+
+        /* Nor for players that do not report a position */
+        let mut changed = PropMap::new();
+        changed.insert("PlaybackStatus".to_string(), variant("Playing".to_string()));
+        let mut no_position = self::player(&[]);
+        receive(&mut no_position, &source, properties_changed_signal(PLAYER_INTERFACE, changed, &[]));
+
+        assert!(source.asked.borrow().is_empty());
+        assert_eq!(player.state.position, Some(Duration::from_secs(1)));
+        assert_eq!(no_position.state.position, None);
+    }
+
+    #[test]
+    fn seeked_sets_the_position() {
+        let mut player = player(&[("Position", MetadataValue::I64(1_000_000))]);
+        let seeked = signal(MPRIS2_PATH, PLAYER_INTERFACE, "Seeked").append1(90_000_000i64);
+
+        assert!(receive(&mut player, &FakeSource::default(), seeked));
+        assert_eq!(player.state.position, Some(Duration::from_secs(90)));
+    }
+
+    #[test]
+    fn identity_comes_from_the_root_interface() {
+        let mut player = player(&[]);
+        let source = FakeSource::default();
+
+        let mut changed = PropMap::new();
+        changed.insert("Identity".to_string(), variant("Renamed".to_string()));
+        assert!(receive(&mut player, &source, properties_changed_signal(ROOT_INTERFACE, changed, &[])));
+        assert_eq!(player.state.identity, "Renamed");
+
+        let mut changed = PropMap::new();
+        changed.insert("Volume".to_string(), variant(0.2f64));
+        assert!(!receive(&mut player, &source, properties_changed_signal("org.example.Other", changed, &[])));
+        assert_eq!(player.state.volume, None);
+    }
+
+    fn name_owner_changed(name: &str, old_owner: &str, new_owner: &str) -> Option<BusEvent> {
+        let message = signal("/org/freedesktop/DBus", "org.freedesktop.DBus", "NameOwnerChanged").
+            append3(name, old_owner, new_owner);
+        BusEvent::from_message(&message)
+    }
+
+    fn named_player(bus_name: &str, owner: &str) -> TrackedPlayer {
+        TrackedPlayer::from_properties(owner.to_string(), bus_name, bus_name.to_string(), Properties::new())
+    }
+
+    fn bus_names(tracked: &[TrackedPlayer]) -> Vec<&str> {
+        tracked.iter().map(|p| p.state.bus_name.as_str()).collect()
+    }
+
+    #[test]
+    fn name_owner_changes_become_appear_and_vanish() {
+        let name = "org.mpris.MediaPlayer2.vlc";
+
+        assert!(matches!(name_owner_changed(name, "", ":1.5"), Some(BusEvent::PlayerAppeared(n)) if n == name));
+        assert!(matches!(name_owner_changed(name, ":1.5", ""), Some(BusEvent::PlayerVanished(n)) if n == name));
+
+        /* A new owner taking over has to be read again */
+        assert!(matches!(name_owner_changed(name, ":1.5", ":1.6"), Some(BusEvent::PlayerAppeared(n)) if n == name));
+    }
+
+    #[test]
+    fn other_names_are_ignored() {
+        for name in ["org.freedesktop.Notifications", ":1.5", "org.mpris.MediaPlayer2"] {
+            assert!(name_owner_changed(name, "", ":1.5").is_none(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn appearing_players_are_kept_sorted() {
+        let mut tracked = vec![];
+        for name in ["org.mpris.MediaPlayer2.vlc", "org.mpris.MediaPlayer2.Lollypop", "org.mpris.MediaPlayer2.amarok"] {
+            player_appeared(&mut tracked, name, Some(named_player(name, ":1.1")));
+        }
+
+        assert_eq!(bus_names(&tracked), [
+            "org.mpris.MediaPlayer2.amarok", "org.mpris.MediaPlayer2.Lollypop", "org.mpris.MediaPlayer2.vlc"
+        ]);
+    }
+
+    #[test]
+    fn hand_over_replaces_the_player() {
+        let name = "org.mpris.MediaPlayer2.vlc";
+        let mut tracked = vec![named_player("org.mpris.MediaPlayer2.amarok", ":1.1"), named_player(name, ":1.2")];
+
+        player_appeared(&mut tracked, name, Some(named_player(name, ":1.3")));
+        assert_eq!(bus_names(&tracked), ["org.mpris.MediaPlayer2.amarok", name]);
+        assert_eq!(tracked[1].owner, ":1.3");
+
+        /* The old owner's state is gone even if the new one cannot be read */
+        player_appeared(&mut tracked, name, None);
+        assert_eq!(bus_names(&tracked), ["org.mpris.MediaPlayer2.amarok"]);
+    }
+
+    #[test]
+    fn vanishing_players_are_removed() {
+        let mut tracked = vec![
+            named_player("org.mpris.MediaPlayer2.amarok", ":1.1"),
+            named_player("org.mpris.MediaPlayer2.vlc", ":1.2"),
+        ];
+
+        assert!(player_vanished(&mut tracked, "org.mpris.MediaPlayer2.amarok"));
+        assert_eq!(bus_names(&tracked), ["org.mpris.MediaPlayer2.vlc"]);
+
+        assert!(!player_vanished(&mut tracked, "org.mpris.MediaPlayer2.amarok"));
+        assert_eq!(bus_names(&tracked), ["org.mpris.MediaPlayer2.vlc"]);
+    }
+}
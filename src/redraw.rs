@@ -0,0 +1,110 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Decides when the overlay needs a new frame, so the UI is only run and
+/// painted after input, player changes or repaint requests from egui.
+///
+/// Clones share the same schedule, which lets egui's repaint callback ask for
+/// a frame from the threads loading images.
+#[derive(Clone)]
+pub struct RedrawScheduler {
+    next: Arc<Mutex<Option<Instant>>>,
+}
+
+impl RedrawScheduler {
+    /// A scheduler with the first frame due right away.
+    pub fn new() -> RedrawScheduler {
+        RedrawScheduler { next: Arc::new(Mutex::new(Some(Instant::now()))) }
+    }
+
+    /// Asks for a frame as soon as possible.
+    pub fn request(&self) {
+        self.request_after(Duration::ZERO);
+    }
+
+    /// Asks for a frame in `delay` at the latest. `Duration::MAX`, which egui
+    /// uses for "no repaint needed", does not schedule anything.
+    pub fn request_after(&self, delay: Duration) {
+        let Some(at) = Instant::now().checked_add(delay) else { return };
+
+        let mut next = self.next.lock().unwrap();
+        *next = Some(next.map_or(at, |next| next.min(at)));
+    }
+
+    /// Whether a frame is due now, clearing the request if so. Anything asked
+    /// for while that frame runs schedules the one after it.
+    pub fn take_due(&self) -> bool {
+        let mut next = self.next.lock().unwrap();
+        if next.is_some_and(|next| next <= Instant::now()) {
+            *next = None;
+            true
+        }
+        else {
+            false
+        }
+    }
+}
+
+impl Default for RedrawScheduler {
+    fn default() -> RedrawScheduler {
+        RedrawScheduler::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A scheduler with nothing due yet.
+    fn idle() -> RedrawScheduler {
+        let redraw = RedrawScheduler::new();
+        assert!(redraw.take_due());
+        redraw
+    }
+
+    #[test]
+    fn first_frame_is_due_and_then_cleared() {
+        let redraw = RedrawScheduler::new();
+        assert!(redraw.take_due());
+        assert!(!redraw.take_due());
+    }
+
+    #[test]
+    fn no_repaint_needed_schedules_nothing() {
+        let redraw = idle();
+        redraw.request_after(Duration::MAX);
+        assert!(redraw.next.lock().unwrap().is_none());
+    }
+
+    #[test]
+    fn earlier_request_wins() {
+        let redraw = idle();
+        redraw.request_after(Duration::from_secs(60));
+        redraw.request_after(Duration::from_secs(1));
+        redraw.request_after(Duration::from_secs(30));
+
+        let next = redraw.next.lock().unwrap().expect("Nothing scheduled");
+        assert!(next <= Instant::now() + Duration::from_secs(1));
+    }
+
+    #[test]
+    fn frame_is_only_due_after_its_deadline() {
+        let redraw = idle();
+        redraw.request_after(Duration::from_millis(50));
+        assert!(!redraw.take_due());
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(redraw.take_due());
+        assert!(!redraw.take_due());
+    }
+
+    #[test]
+    fn clones_share_the_schedule() {
+        let redraw = idle();
+        let clone = redraw.clone();
+
+        clone.request();
+        assert!(redraw.take_due());
+        assert!(!clone.take_due());
+    }
+}
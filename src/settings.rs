@@ -0,0 +1,118 @@
+use egui_sdl2_gl::egui;
+
+use crate::config::{Config, Theme};
+
+/// The settings tab. Edits go to a copy of the configuration, which only
+/// replaces the real one once applied, so that e.g. the UI scale does not
+/// change under the laser while its slider is dragged.
+pub struct SettingsPage {
+    saved: Config,
+    draft: Config,
+    error: Option<String>,
+}
+
+impl SettingsPage {
+    pub fn new(config: &Config) -> SettingsPage {
+        SettingsPage { saved: config.clone(), draft: config.clone(), error: None }
+    }
+
+    /// Follows the configuration now in effect, dropping unapplied edits.
+    pub fn set_config(&mut self, config: &Config) {
+        if *config != self.saved {
+            self.saved = config.clone();
+            self.draft = config.clone();
+        }
+    }
+
+    /// Shows why the last settings could not be applied.
+    pub fn set_error(&mut self, error: Option<String>) {
+        self.error = error;
+    }
+
+    /// `players` are the identities of the players currently on the bus.
+    /// Returns the edited configuration when the user applies it.
+    pub fn show(&mut self, ui: &mut egui::Ui, players: &[String]) -> Option<Config> {
+        let draft = &mut self.draft;
+
+        egui::Grid::new("settings").num_columns(2).striped(true).show(ui, |ui| {
+            ui.label("Overlay width");
+            ui.add(egui::Slider::new(&mut draft.overlay.width_in_meters, 0.5..=4.0).suffix(" m"));
+            ui.end_row();
+
+            ui.label("UI scale");
+            ui.add(egui::Slider::new(&mut draft.overlay.ui_scale, 1.0..=8.0));
+            ui.end_row();
+
+            ui.label("Theme");
+            ui.horizontal(|ui| {
+                ui.selectable_value(&mut draft.overlay.theme, Theme::Dark, "Dark");
+                ui.selectable_value(&mut draft.overlay.theme, Theme::Light, "Light");
+            });
+            ui.end_row();
+
+            ui.label("Checks while hidden");
+            ui.add(egui::Slider::new(&mut draft.overlay.hidden_poll_interval_ms, 50..=2000).
+                   suffix(" ms").logarithmic(true));
+            ui.end_row();
+
+            ui.label("Start with SteamVR");
+            let mut autostart = draft.overlay.autostart.unwrap_or(false);
+            if ui.checkbox(&mut autostart, "").changed() {
+                draft.overlay.autostart = Some(autostart);
+            }
+            ui.end_row();
+        });
+
+        ui.separator();
+        ui.label("Shown players");
+
+        /* Hidden players that are not running can still be shown again */
+        let mut identities: Vec<&String> = players.iter().chain(draft.players.hidden.iter()).collect();
+        identities.sort();
+        identities.dedup();
+
+        let mut hidden = draft.players.hidden.clone();
+        for identity in identities {
+            let mut shown = !hidden.contains(identity);
+            if ui.checkbox(&mut shown, identity.as_str()).changed() {
+                if shown {
+                    hidden.retain(|h| h != identity);
+                }
+                else {
+                    hidden.push(identity.clone());
+                }
+            }
+        }
+        draft.players.hidden = hidden;
+
+        ui.separator();
+
+        let mut applied = None;
+
+        ui.horizontal(|ui| {
+            let changed = self.draft != self.saved;
+
+            if ui.add_enabled(changed, egui::Button::new("Apply")).clicked() {
+                match self.draft.validate() {
+                    Ok(()) => {
+                        self.saved = self.draft.clone();
+                        self.error = None;
+                        applied = Some(self.draft.clone());
+                    },
+                    Err(err) => { self.error = Some(err); },
+                }
+            }
+
+            if ui.add_enabled(changed, egui::Button::new("Revert")).clicked() {
+                self.draft = self.saved.clone();
+                self.error = None;
+            }
+        });
+
+        if let Some(error) = &self.error {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+        }
+
+        applied
+    }
+}
@@ -0,0 +1,267 @@
+use std::collections::VecDeque;
+use std::mem::ManuallyDrop;
+
+use openvr_sys2::{
+    VROverlayFlags,
+    EVREventType,
+    EVREventType::*,
+    VREvent_t,
+};
+
+use egui_sdl2_gl::gl::types::GLuint;
+
+use crate::cursor::Cursor;
+use crate::vr::VrRuntime;
+
+/// An overlay property set through a `VrRuntime`, in the order the calls were
+/// made.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OverlayChange {
+    Thumbnail(String),
+    InputMethodMouse,
+    /// The raw `VROverlayFlags` value, since the generated enum cannot be
+    /// printed.
+    Flag(u32, bool),
+    MouseScale([f32; 2]),
+    WidthInMeters(f32),
+    Texture(GLuint),
+    KeyboardShown,
+    KeyboardHidden,
+    Cursor(Option<Cursor>),
+    CursorPosition(Option<[f32; 2]>),
+    Autostart(bool),
+    /// Not a property, but it has to come after `VREvent_Quit` all the same.
+    QuitAcknowledged,
+}
+
+/// Runtime without SteamVR that replays scripted frames of events.
+///
+/// Each call to `wait_frame_sync` or `wait_events` moves on to the next
+/// frame, so a button press and its release can be delivered on separate
+/// frames like they would be by a controller. Once the script runs out the
+/// runtime asks the overlay to quit, so a loop driven by it always terminates.
+///
+/// The overlay starts out visible and follows the `VREvent_OverlayShown` and
+/// `VREvent_OverlayHidden` events in the script. SteamVR keeps running until a
+/// frame queued with `push_vanish_frame`.
+pub struct SimulatedRuntime {
+    changes: Vec<OverlayChange>,
+    frames: VecDeque<Frame>,
+    current: Frame,
+    frame_count: u64,
+    idle_frame_count: u64,
+    visible: bool,
+    running: bool,
+}
+
+#[derive(Default)]
+struct Frame {
+    system_events: VecDeque<VREvent_t>,
+    overlay_events: VecDeque<VREvent_t>,
+    vanish: bool,
+}
+
+impl SimulatedRuntime {
+    pub fn new() -> SimulatedRuntime {
+        SimulatedRuntime {
+            changes: vec![],
+            frames: VecDeque::new(),
+            current: Frame::default(),
+            frame_count: 0,
+            idle_frame_count: 0,
+            visible: true,
+            running: true,
+        }
+    }
+
+    fn next_frame(&mut self) {
+        self.current = self.frames.pop_front().unwrap_or_else(|| Frame {
+            system_events: VecDeque::from([event(VREvent_Quit)]),
+            ..Frame::default()
+        });
+        self.running &= !self.current.vanish;
+        self.frame_count += 1;
+    }
+
+    /// Queues a frame whose overlay events are `events`.
+    pub fn push_frame(&mut self, events: Vec<VREvent_t>) {
+        self.frames.push_back(Frame {
+            overlay_events: events.into(),
+            ..Frame::default()
+        });
+    }
+
+    /// Queues a frame with only a system event, such as `VREvent_Quit`.
+    pub fn push_system_frame(&mut self, event: VREvent_t) {
+        self.frames.push_back(Frame {
+            system_events: VecDeque::from([event]),
+            ..Frame::default()
+        });
+    }
+
+    /// Queues a frame from which on SteamVR is gone, without having sent
+    /// `VREvent_Quit`.
+    pub fn push_vanish_frame(&mut self) {
+        self.frames.push_back(Frame { vanish: true, ..Frame::default() });
+    }
+
+    pub fn changes(&self) -> &[OverlayChange] {
+        &self.changes
+    }
+
+    /// Number of frames the loop waited for so far.
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// Number of those frames the loop spent idling in `wait_events`.
+    pub fn idle_frame_count(&self) -> u64 {
+        self.idle_frame_count
+    }
+}
+
+impl Default for SimulatedRuntime {
+    fn default() -> SimulatedRuntime {
+        SimulatedRuntime::new()
+    }
+}
+
+impl VrRuntime for SimulatedRuntime {
+    fn set_thumbnail(&mut self, path: &str) {
+        self.changes.push(OverlayChange::Thumbnail(path.to_string()));
+    }
+
+    fn set_input_method_mouse(&mut self) {
+        self.changes.push(OverlayChange::InputMethodMouse);
+    }
+
+    fn set_flag(&mut self, flag: VROverlayFlags, enabled: bool) {
+        self.changes.push(OverlayChange::Flag(flag as u32, enabled));
+    }
+
+    fn set_mouse_scale(&mut self, scale: [f32; 2]) {
+        self.changes.push(OverlayChange::MouseScale(scale));
+    }
+
+    fn set_width_in_meters(&mut self, width: f32) {
+        self.changes.push(OverlayChange::WidthInMeters(width));
+    }
+
+    fn submit_texture(&mut self, texture: GLuint) {
+        self.changes.push(OverlayChange::Texture(texture));
+    }
+
+    fn poll_system_event(&mut self) -> Option<VREvent_t> {
+        self.current.system_events.pop_front()
+    }
+
+    fn poll_overlay_event(&mut self) -> Option<VREvent_t> {
+        let event = self.current.overlay_events.pop_front()?;
+
+        if event.eventType == VREvent_OverlayShown as u32 {
+            self.visible = true;
+        }
+        else if event.eventType == VREvent_OverlayHidden as u32 {
+            self.visible = false;
+        }
+
+        Some(event)
+    }
+
+    fn acknowledge_quit(&mut self) {
+        self.changes.push(OverlayChange::QuitAcknowledged);
+    }
+
+    fn is_running(&mut self) -> bool {
+        self.running
+    }
+
+    fn wait_frame_sync(&mut self, _timeout_ms: u32) {
+        self.next_frame();
+    }
+
+    fn wait_events(&mut self, _timeout_ms: u32) {
+        self.next_frame();
+        self.idle_frame_count += 1;
+    }
+
+    fn is_visible(&mut self) -> bool {
+        self.visible
+    }
+
+    fn show_keyboard(&mut self) {
+        self.changes.push(OverlayChange::KeyboardShown);
+    }
+
+    fn hide_keyboard(&mut self) {
+        self.changes.push(OverlayChange::KeyboardHidden);
+    }
+
+    fn set_cursor(&mut self, cursor: Option<Cursor>) {
+        self.changes.push(OverlayChange::Cursor(cursor));
+    }
+
+    fn set_cursor_position(&mut self, position: Option<[f32; 2]>) {
+        self.changes.push(OverlayChange::CursorPosition(position));
+    }
+
+    fn set_autostart(&mut self, enabled: bool) {
+        self.changes.push(OverlayChange::Autostart(enabled));
+    }
+}
+
+/// An event of the given type with no data.
+pub fn event(event_type: EVREventType) -> VREvent_t {
+    let mut event: VREvent_t = unsafe { std::mem::zeroed() };
+    event.eventType = event_type as u32;
+    event
+}
+
+/// `VREvent_MouseMove`, `VREvent_MouseButtonDown` or `VREvent_MouseButtonUp`
+/// at overlay coordinates `x`, `y` (origin at the bottom left). `button` is one
+/// of the `input::MOUSE_BUTTON_*` values.
+pub fn mouse_event(event_type: EVREventType, x: f32, y: f32, button: u32) -> VREvent_t {
+    let mut event = event(event_type);
+    event.data = openvr_sys2::VREvent_Data_t {
+        mouse: ManuallyDrop::new(openvr_sys2::VREvent_Mouse_t { x, y, button })
+    };
+    event
+}
+
+/// A button press followed by its release, which egui reads as a click.
+pub fn click_events(x: f32, y: f32, button: u32) -> Vec<VREvent_t> {
+    vec![
+        mouse_event(VREvent_MouseMove, x, y, 0),
+        mouse_event(VREvent_MouseButtonDown, x, y, button),
+        mouse_event(VREvent_MouseButtonUp, x, y, button),
+    ]
+}
+
+pub fn scroll_event(xdelta: f32, ydelta: f32) -> VREvent_t {
+    let mut event = event(VREvent_ScrollSmooth);
+    event.data = openvr_sys2::VREvent_Data_t {
+        scroll: ManuallyDrop::new(openvr_sys2::VREvent_Scroll_t {
+            xdelta, ydelta, unused: 0, viewportscale: 1.0
+        })
+    };
+    event
+}
+
+/// `VREvent_KeyboardCharInput` carrying up to 8 bytes of input, split the way
+/// SteamVR splits it, even in the middle of a character.
+pub fn keyboard_events(input: &[u8]) -> Vec<VREvent_t> {
+    input.chunks(8).map(|chunk| {
+        let mut new_input = [0; 8];
+        for (dst, &src) in new_input.iter_mut().zip(chunk) {
+            *dst = src as std::ffi::c_char;
+        }
+
+        let mut event = event(VREvent_KeyboardCharInput);
+        event.data = openvr_sys2::VREvent_Data_t {
+            keyboard: ManuallyDrop::new(openvr_sys2::VREvent_Keyboard_t {
+                cNewInput: new_input, uUserValue: 0
+            })
+        };
+        event
+    }).collect()
+}
@@ -0,0 +1,159 @@
+//! Checks the layout of the overlay against references in `snapshots/`.
+//!
+//! Each reference lists the text the overlay draws for one canned set of
+//! players, with where it is drawn. Only text is compared: the icons come from
+//! the installed theme, so they would make the references differ between
+//! machines. Changes to anything else (images, sliders, buttons, colours) are
+//! therefore only caught when they move some text, e.g. the cover art pushing
+//! the player's controls aside. Run the tests with `UPDATE_SNAPSHOTS=1` to replace the references
+//! after an intended change; otherwise a layout that does not match is written
+//! next to its reference as `<name>.actual.txt`.
+
+use egui_sdl2_gl::egui;
+
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::backend::PlayerState;
+use crate::config::Config;
+use crate::fake::{FakeBackend, fake_player, fake_metadata};
+use crate::ui::OverlayUi;
+
+/// Frames to run before taking the layout, since some widgets (e.g. side
+/// panels) only settle on their size in the frame after the first.
+const SETTLE_FRAMES: usize = 2;
+
+/// How long to wait for the cover art to be loaded before giving up.
+const ART_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Cover art that is always there, so its layout does not depend on the machine.
+fn art_url() -> String {
+    format!("file://{}/assets/icon.png", env!("CARGO_MANIFEST_DIR"))
+}
+
+/// Canned player states covering the layouts the panel has to handle.
+fn fixtures() -> Vec<(&'static str, Vec<PlayerState>)> {
+    let no_art = fake_player("NoArt", "No Art");
+
+    let mut art = fake_player("Art", "Art");
+    art.metadata = fake_metadata(
+        "/track/1", "Track", &["Artist"], Some(Duration::from_secs(200)), Some(&art_url()));
+
+    let mut long_title = fake_player("LongTitle", "A Player With An Unreasonably Long Identity");
+    long_title.metadata = fake_metadata(
+        "/track/2",
+        "An Extremely Long Track Title That Goes On And On Well Past The Edge Of The Overlay",
+        &["First Artist", "Second Artist", "Third Artist"],
+        Some(Duration::from_secs(3 * 3600 + 25 * 60 + 7)), None);
+
+    let mut live_stream = fake_player("Stream", "Radio");
+    live_stream.metadata = fake_metadata("/track/3", "Live Stream", &[], None, None);
+
+    let mut no_volume = fake_player("NoVolume", "No Volume");
+    no_volume.volume = None;
+
+    let mut shuffle_loop = fake_player("ShuffleLoop", "Shuffle And Loop");
+    shuffle_loop.shuffle = Some(true);
+    shuffle_loop.loop_status = Some(mpris::LoopStatus::Playlist);
+
+    vec![
+        ("no_players", vec![]),
+        ("no_art", vec![no_art]),
+        ("art", vec![art]),
+        ("long_title", vec![long_title]),
+        ("live_stream", vec![live_stream]),
+        ("no_volume", vec![no_volume]),
+        ("shuffle_loop", vec![shuffle_loop]),
+    ]
+}
+
+/// Shows the overlay at the default size and scale, so that the configuration
+/// does not change the references, and lists the text it draws, one line per
+/// piece of text in drawing order, with its rectangle in points.
+fn layout(players: Vec<PlayerState>) -> String {
+    let config = Config::default();
+    let scale = config.overlay.ui_scale as f32;
+    let screen_size = egui::vec2(config.overlay.width as f32, config.overlay.height as f32) / scale;
+
+    let ctx = egui::Context::default();
+    egui_extras::install_image_loaders(&ctx);
+    wait_for_art(&ctx, &players);
+    let mut backend = FakeBackend::new(players);
+    let mut overlay_ui = OverlayUi::new(&ctx, &config);
+    overlay_ui.update(&mut backend);
+
+    let mut output = egui::FullOutput::default();
+    for _ in 0..SETTLE_FRAMES {
+        let input = egui::RawInput {
+            screen_rect: Some(egui::Rect::from_min_size(egui::Pos2::ZERO, screen_size)),
+            pixels_per_point: Some(scale),
+            ..Default::default()
+        };
+
+        output = ctx.run(input, |ctx| { overlay_ui.show(ctx, &mut backend); });
+    }
+
+    let mut layout = String::new();
+    for clipped in output.shapes.iter() {
+        if let egui::Shape::Text(shape) = &clipped.shape {
+            let rect = shape.galley.rect.translate(shape.pos.to_vec2());
+            writeln!(layout, "{:.1},{:.1} {:.1}x{:.1} {:?}",
+                     rect.min.x, rect.min.y, rect.width(), rect.height(), shape.galley.text()).
+                expect("Failed to format layout");
+        }
+    }
+
+    layout
+}
+
+/// Loads the players' cover art up front, since it is loaded in the background
+/// and the layout would otherwise depend on how fast that is.
+fn wait_for_art(ctx: &egui::Context, players: &[PlayerState]) {
+    for url in players.iter().filter_map(|p| p.metadata.art_url()) {
+        let deadline = Instant::now() + ART_TIMEOUT;
+        loop {
+            match ctx.try_load_image(url, egui::SizeHint::default()) {
+                Ok(egui::load::ImagePoll::Ready { .. }) => break,
+                Ok(egui::load::ImagePoll::Pending { .. }) if Instant::now() < deadline => {
+                    std::thread::sleep(Duration::from_millis(10));
+                },
+                result => panic!("Failed to load {}: {:?}", url, result.err()),
+            }
+        }
+    }
+}
+
+fn snapshot_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("snapshots")
+}
+
+#[test]
+fn layouts_match_snapshots() {
+    let dir = snapshot_dir();
+    let update = std::env::var_os("UPDATE_SNAPSHOTS").is_some();
+    let mut mismatched = vec![];
+
+    for (name, players) in fixtures() {
+        let actual = layout(players);
+
+        let reference_path = dir.join(format!("{}.txt", name));
+        let actual_path = dir.join(format!("{}.actual.txt", name));
+
+        if update {
+            std::fs::write(&reference_path, &actual).expect("Failed to write snapshot");
+            continue;
+        }
+
+        let reference = std::fs::read_to_string(&reference_path).unwrap_or_default();
+        if reference == actual {
+            let _ = std::fs::remove_file(&actual_path);
+        }
+        else {
+            std::fs::write(&actual_path, &actual).expect("Failed to write snapshot");
+            mismatched.push(actual_path.display().to_string());
+        }
+    }
+
+    assert!(mismatched.is_empty(), "Layouts differ from their snapshots, see {}", mismatched.join(", "));
+}
@@ -0,0 +1,166 @@
+use dbus::ffidisp::{BusType, Connection, NameFlag, RequestNameReply};
+
+use sd_notify::NotifyState;
+
+use std::path::Path;
+use std::time::Duration;
+
+use crate::manifest::quote_argument;
+
+/// The user unit `install --systemd` writes.
+pub const UNIT_NAME: &str = "mpris-openvr-overlay.service";
+
+/// Owned by `run --daemon --dbus-activation` on the session bus, so that D-Bus
+/// can start the unit when something asks for it.
+pub const BUS_NAME: &str = "org.mon_ouie.mpris_openvr_overlay";
+
+/// Bounds for the wait between attempts to own `BUS_NAME`, doubling after
+/// each failure.
+const MIN_CLAIM_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_CLAIM_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How often the connection owning `BUS_NAME` checks that it is still up.
+const CONNECTION_CHECK_MS: u32 = 1000;
+
+/// The daemon command line, quoted for systemd.
+fn command_line(binary_path: &Path, config_path: Option<&Path>, dbus_activation: bool) -> String {
+    let mut args = vec![
+        quote_argument(&binary_path.to_string_lossy()),
+        "run".to_string(),
+        "--daemon".to_string(),
+    ];
+
+    if dbus_activation {
+        args.push("--dbus-activation".to_string());
+    }
+
+    if let Some(config_path) = config_path {
+        args.push("--config".to_string());
+        args.push(quote_argument(&config_path.to_string_lossy()));
+    }
+
+    args.join(" ")
+}
+
+/// A unit that starts the overlay with the graphical session and restarts it
+/// if it fails. Paths have to be absolute.
+pub fn unit(binary_path: &Path, config_path: Option<&Path>, dbus_activation: bool) -> String {
+    let mut unit = String::new();
+
+    unit.push_str("[Unit]\n");
+    unit.push_str("Description=Media player controls for the SteamVR dashboard\n");
+    unit.push_str("PartOf=graphical-session.target\n");
+    unit.push_str("After=graphical-session.target\n");
+    unit.push('\n');
+
+    unit.push_str("[Service]\n");
+    unit.push_str("Type=notify\n");
+    /* systemd expands specifiers such as %h and environment variables */
+    let exec_start = command_line(binary_path, config_path, dbus_activation).replace('%', "%%").replace('$', "$$");
+    unit.push_str(&format!("ExecStart={}\n", exec_start));
+    unit.push_str("Restart=on-failure\n");
+    unit.push_str("RestartSec=5\n");
+    if dbus_activation {
+        unit.push_str(&format!("BusName={}\n", BUS_NAME));
+    }
+    unit.push('\n');
+
+    unit.push_str("[Install]\n");
+    unit.push_str("WantedBy=graphical-session.target\n");
+
+    unit
+}
+
+/// A D-Bus service file that activates the unit through systemd. The bus
+/// only runs `Exec` without systemd, which the unit needs anyway.
+pub fn dbus_service() -> String {
+    format!("[D-BUS Service]\nName={}\nExec=/bin/false\nSystemdService={}\n", BUS_NAME, UNIT_NAME)
+}
+
+/// Tells systemd that the service started, when running as one.
+pub fn notify_ready() {
+    let _ = sd_notify::notify(false, &[NotifyState::Ready]);
+}
+
+/// Shown by `systemctl status`, when running as a service.
+pub fn notify_status(status: &str) {
+    let _ = sd_notify::notify(false, &[NotifyState::Status(status)]);
+}
+
+/// Owns `BUS_NAME` for as long as the returned connection is kept.
+fn claim_bus_name() -> Result<Connection, String> {
+    let connection = Connection::get_private(BusType::Session).map_err(|err| err.to_string())?;
+
+    match connection.register_name(BUS_NAME, NameFlag::DoNotQueue.value()) {
+        Ok(RequestNameReply::PrimaryOwner) | Ok(RequestNameReply::AlreadyOwner) => Ok(connection),
+        Ok(_) => Err(format!("{} is owned by another process", BUS_NAME)),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// Keeps owning `BUS_NAME` from a background thread for as long as the
+/// process runs, trying again while the session bus is away or another
+/// process owns the name, and claiming it again after losing the bus.
+pub fn spawn_bus_name_owner() {
+    std::thread::Builder::new().
+        name("bus-name-owner".to_string()).
+        spawn(own_bus_name).
+        expect("Failed to spawn bus name owner");
+}
+
+fn own_bus_name() {
+    let mut interval = MIN_CLAIM_INTERVAL;
+    let mut last_error = None;
+
+    loop {
+        match claim_bus_name() {
+            Ok(connection) => {
+                log::info!("Owning {}", BUS_NAME);
+                interval = MIN_CLAIM_INTERVAL;
+                last_error = None;
+
+                /* Reading is what notices the bus going away */
+                while connection.is_connected() {
+                    for _ in connection.incoming(CONNECTION_CHECK_MS) {}
+                }
+
+                log::warn!("Lost the connection owning {}", BUS_NAME);
+            },
+            Err(err) => {
+                /* Only once while it keeps failing the same way */
+                if last_error.as_ref() != Some(&err) {
+                    log::warn!("Not owning {}: {}", BUS_NAME, err);
+                }
+                last_error = Some(err);
+
+                std::thread::sleep(interval);
+                interval = (interval * 2).min(MAX_CLAIM_INTERVAL);
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exec_start_escapes_specifiers_and_variables() {
+        let unit = unit(Path::new("/opt/100% $HOME/overlay"), Some(Path::new("/home/user/$dir/%h.toml")), false);
+
+        assert!(unit.lines().any(|line| {
+            line == r#"ExecStart="/opt/100%% $$HOME/overlay" run --daemon --config /home/user/$$dir/%%h.toml"#
+        }), "{}", unit);
+    }
+
+    #[test]
+    fn only_activated_units_own_the_bus_name() {
+        let activated = unit(Path::new("/usr/bin/overlay"), None, true);
+        assert!(activated.contains("\nExecStart=/usr/bin/overlay run --daemon --dbus-activation\n"), "{}", activated);
+        assert!(activated.contains(&format!("\nBusName={}\n", BUS_NAME)), "{}", activated);
+
+        let plain = unit(Path::new("/usr/bin/overlay"), None, false);
+        assert!(plain.contains("\nExecStart=/usr/bin/overlay run --daemon\n"), "{}", plain);
+        assert!(!plain.contains("BusName="), "{}", plain);
+    }
+}
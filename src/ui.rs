@@ -0,0 +1,448 @@
+use egui_sdl2_gl::egui;
+
+use freedesktop_icons::lookup as icon_lookup;
+
+use crate::backend::{MediaBackend, PlayerCommand, PlayerState};
+use crate::config::{Config, Theme};
+use crate::settings::SettingsPage;
+
+pub fn icon_path(icon_name: &str) -> Option<String> {
+    ["default", "hicolor", "gnome", "oxygen"].iter().find_map(|theme| {
+        let path_buf = icon_lookup(icon_name).with_theme(theme).with_cache().find()?;
+        path_buf.into_os_string().into_string().ok()
+    })
+}
+
+fn icon_uri(icon_name: &str) -> Option<String> {
+    icon_path(icon_name).map(|path| {
+        "file://".to_owned() + &path
+    })
+}
+
+/// The text field focused during the frame that produced `output`, if any.
+///
+/// egui does not say what kind of field it is or what it limits its length
+/// to, which is why the SteamVR keyboard does not follow either. The only
+/// fields are the values of sliders, which are single-line anyway.
+pub fn focused_text_field(ctx: &egui::Context, output: &egui::PlatformOutput) -> Option<egui::Id> {
+    /* egui only places a text cursor while a text field has focus */
+    output.text_cursor_pos?;
+    ctx.memory(|memory| memory.focus())
+}
+
+/// How often the position slider moves while a player is playing.
+const POSITION_REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
+
+fn format_time(x: f64) -> String {
+    let total_secs = x as u64;
+
+    let secs = total_secs % 60;
+    let minutes = (total_secs / 60) % 60;
+    let hours = (total_secs / 60) / 60;
+
+    if hours == 0 {
+        format!("{:0>2}:{:0>2}", minutes, secs)
+    }
+    else {
+        format!("{:0>2}:{:0>2}:{:0>2}", hours, minutes, secs)
+    }
+}
+
+/// The media player controls, backed by the latest player snapshots of a
+/// `MediaBackend`.
+#[derive(Default)]
+pub struct PlayerPanel {
+    /// Every player the backend knows about, including hidden ones.
+    all_players: Vec<PlayerState>,
+    players: Vec<PlayerState>,
+    /// Identities of the players to leave out.
+    hidden: Vec<String>,
+    selected_player_id: usize,
+    /// Set while the backend cannot reach any player.
+    connection_error: Option<String>,
+}
+
+impl PlayerPanel {
+    pub fn selected_player(&self) -> Option<&PlayerState> {
+        self.players.get(self.selected_player_id)
+    }
+
+    /// Identities of all players, hidden or not.
+    pub fn identities(&self) -> Vec<String> {
+        self.all_players.iter().map(|p| p.identity.clone()).collect()
+    }
+
+    pub fn set_hidden(&mut self, hidden: &[String]) {
+        self.hidden = hidden.to_vec();
+        self.filter_players();
+    }
+
+    /// Picks up new player snapshots, keeping the same player selected if it
+    /// is still around. Returns whether there were any.
+    pub fn update(&mut self, backend: &mut dyn MediaBackend) -> bool {
+        let Some(new_players) = backend.poll_players() else { return false };
+
+        self.all_players = new_players;
+        self.connection_error = backend.connection_error();
+        self.filter_players();
+
+        true
+    }
+
+    fn filter_players(&mut self) {
+        let old_player_bus_name = self.selected_player().map(|p| p.bus_name.clone());
+
+        self.players = self.all_players.iter().
+            filter(|p| !self.hidden.contains(&p.identity)).cloned().collect();
+        self.selected_player_id = 0;
+
+        if let Some(bus) = old_player_bus_name {
+            if let Some(id) = self.players.iter().position(|p| p.bus_name == bus) {
+                self.selected_player_id = id;
+            }
+        }
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context, backend: &mut dyn MediaBackend) {
+        if let Some(err) = &self.connection_error {
+            egui::CentralPanel::default().show(ctx, |ui| {
+                ui.heading("Not connected to D-Bus");
+                ui.label(err);
+                ui.weak("Reconnecting…");
+            });
+            return;
+        }
+
+        if self.players.is_empty() {
+            return;
+        }
+
+        if self.selected_player_id >= self.players.len() {
+            self.selected_player_id = self.players.len() - 1;
+        }
+
+        let players = &self.players;
+        let selected_player_id = &mut self.selected_player_id;
+
+        let selected_player = &players[*selected_player_id];
+        let bus_name = selected_player.bus_name.clone();
+        let metadata = &selected_player.metadata;
+
+        let mut commands = vec![];
+
+        egui::SidePanel::left("icon").show(ctx, |ui| {
+            if let Some(url) = metadata.art_url() {
+                ui.add(egui::Image::new(url).show_loading_spinner(true).shrink_to_fit());
+            }
+        });
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            egui::ScrollArea::horizontal().show(ui, |ui| {
+                egui::ComboBox::from_label("Player").
+                    selected_text(&selected_player.identity).
+                    show_index(
+                        ui,
+                        selected_player_id,
+                        players.len(),
+                        |i| &players[i].identity
+                    );
+            });
+            ui.separator();
+
+            let song_name = metadata.title().unwrap_or("?");
+            let artists = metadata.artists().map(|x| x.join(", ")).map(|x| {
+                if x.is_empty() { x } else { x + " - " }
+            }).unwrap_or("".to_string());
+            let shown_name = artists + song_name;
+            ui.label(shown_name);
+
+            if let Some(old_volume) = selected_player.volume {
+                let mut volume = old_volume;
+
+                ui.horizontal(|ui| {
+                    ui.label("Volume");
+                    ui.add(egui::Slider::new(&mut volume, 0.0..=1.0).
+                           show_value(false).
+                           trailing_fill(true));
+                });
+
+                if volume != old_volume {
+                    commands.push(PlayerCommand::SetVolume(volume));
+                }
+            }
+
+            if let Some(pos) = selected_player.current_position() {
+                /* The position moves on its own while playing */
+                if selected_player.playback_status == mpris::PlaybackStatus::Playing {
+                    ctx.request_repaint_after(POSITION_REFRESH_INTERVAL);
+                }
+
+                let duration = metadata.length().unwrap_or(std::time::Duration::ZERO);
+                let mut out_pos = pos.as_secs_f64();
+
+                ui.horizontal(|ui| {
+                    ui.add(egui::Slider::new(&mut out_pos, 0.0..=duration.as_secs_f64()).
+                           custom_formatter(|x, _| format_time(x)).
+                           trailing_fill(true));
+                    ui.label(format_time(duration.as_secs_f64()));
+                });
+
+                if out_pos != pos.as_secs_f64() {
+                    if let Some(id) = metadata.track_id() {
+                        let out_pos = std::time::Duration::from_secs_f64(out_pos);
+                        commands.push(PlayerCommand::SetPosition(id, out_pos));
+                    }
+                }
+            }
+
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                let pause = selected_player.playback_status == mpris::PlaybackStatus::Playing;
+
+                if let Some(icon) = icon_uri(if pause  { "media-playback-pause" } else { "media-playback-start" }) {
+                    if ui.add(egui::ImageButton::new(egui::Image::from_uri(icon))).clicked() {
+                        if pause {
+                            commands.push(PlayerCommand::Pause);
+                        }
+                        else {
+                            commands.push(PlayerCommand::Play);
+                        }
+                    }
+                }
+
+                if selected_player.can_go_previous {
+                    if let Some(icon) = icon_uri("media-skip-backward") {
+                        if ui.add(egui::ImageButton::new(icon)).clicked() {
+                            commands.push(PlayerCommand::Previous);
+                        }
+                    }
+                }
+
+                if selected_player.can_stop {
+                    if let Some(icon) = icon_uri("media-playback-stop") {
+                        if ui.add(egui::ImageButton::new(icon)).clicked() {
+                            commands.push(PlayerCommand::Stop);
+                        }
+                    }
+                }
+
+                if selected_player.can_go_next {
+                    if let Some(icon) = icon_uri("media-skip-forward") {
+                        if ui.add(egui::ImageButton::new(icon)).clicked() {
+                            commands.push(PlayerCommand::Next);
+                        }
+                    }
+                }
+
+                if let Some(shuffle_state) = selected_player.shuffle {
+                    if let Some(icon) = icon_uri("media-playlist-shuffle") {
+                        if ui.add(egui::ImageButton::new(icon).selected(shuffle_state)).clicked() {
+                            commands.push(PlayerCommand::SetShuffle(!shuffle_state));
+                        }
+                    }
+                }
+
+                if let Some(loop_state) = selected_player.loop_status {
+                    if let Some(icon) = icon_uri("media-playlist-repeat") {
+                        if ui.add(egui::ImageButton::new(icon).selected(loop_state != mpris::LoopStatus::None)).clicked() {
+                            if loop_state == mpris::LoopStatus::None {
+                                commands.push(PlayerCommand::SetLoopStatus(mpris::LoopStatus::Track));
+                            }
+                            else {
+                                commands.push(PlayerCommand::SetLoopStatus(mpris::LoopStatus::None));
+                            }
+                        }
+                    }
+                }
+            });
+        });
+
+        /* Keep showing the requested state until the backend reports back */
+        if let Some(player) = self.players.iter_mut().find(|p| p.bus_name == bus_name) {
+            for command in commands.iter() {
+                player.apply(command);
+            }
+        }
+
+        for command in commands {
+            backend.send(&bus_name, command);
+        }
+    }
+}
+
+pub fn apply_theme(ctx: &egui::Context, theme: Theme) {
+    ctx.set_visuals(match theme {
+        Theme::Dark => egui::Visuals::dark(),
+        Theme::Light => egui::Visuals::light(),
+    });
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Tab {
+    Player,
+    Settings,
+}
+
+/// Everything shown on the overlay: the player controls and, on a second
+/// tab, the settings.
+pub struct OverlayUi {
+    pub panel: PlayerPanel,
+    pub settings: SettingsPage,
+    tab: Tab,
+}
+
+impl OverlayUi {
+    pub fn new(ctx: &egui::Context, config: &Config) -> OverlayUi {
+        let mut overlay_ui = OverlayUi {
+            panel: PlayerPanel::default(),
+            settings: SettingsPage::new(config),
+            tab: Tab::Player,
+        };
+
+        overlay_ui.set_config(ctx, config);
+        overlay_ui
+    }
+
+    /// Applies the parts of the configuration the UI itself depends on.
+    pub fn set_config(&mut self, ctx: &egui::Context, config: &Config) {
+        apply_theme(ctx, config.overlay.theme);
+        self.panel.set_hidden(&config.players.hidden);
+        self.settings.set_config(config);
+    }
+
+    pub fn update(&mut self, backend: &mut dyn MediaBackend) -> bool {
+        self.panel.update(backend)
+    }
+
+    /// Returns the new configuration when settings were applied.
+    pub fn show(&mut self, ctx: &egui::Context, backend: &mut dyn MediaBackend) -> Option<Config> {
+        egui::TopBottomPanel::top("tabs").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.selectable_value(&mut self.tab, Tab::Player, "Player");
+                ui.selectable_value(&mut self.tab, Tab::Settings, "Settings");
+            });
+        });
+
+        match self.tab {
+            Tab::Player => {
+                self.panel.show(ctx, backend);
+                None
+            },
+            Tab::Settings => {
+                let identities = self.panel.identities();
+                egui::CentralPanel::default().show(ctx, |ui| {
+                    egui::ScrollArea::vertical().show(ui, |ui| self.settings.show(ui, &identities)).inner
+                }).inner
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::fake::{FakeBackend, fake_player};
+
+    fn show_frame(ctx: &egui::Context, panel: &mut PlayerPanel, backend: &mut FakeBackend,
+                  events: Vec<egui::Event>) -> egui::FullOutput {
+        let input = egui::RawInput {
+            screen_rect: Some(egui::Rect::from_min_size(egui::Pos2::ZERO, egui::vec2(800.0, 300.0))),
+            events,
+            ..Default::default()
+        };
+
+        ctx.run(input, |ctx| panel.show(ctx, backend))
+    }
+
+    /// Where `text` was drawn during the frame that produced `output`.
+    fn text_rect(output: &egui::FullOutput, text: &str) -> Option<egui::Rect> {
+        output.shapes.iter().find_map(|clipped| match &clipped.shape {
+            egui::Shape::Text(shape) if shape.galley.text() == text => {
+                Some(shape.galley.rect.translate(shape.pos.to_vec2()))
+            },
+            _ => None
+        })
+    }
+
+    fn click(pos: egui::Pos2, pressed: bool) -> egui::Event {
+        egui::Event::PointerButton {
+            pos, button: egui::PointerButton::Primary, pressed, modifiers: egui::Modifiers::NONE
+        }
+    }
+
+    fn bus_names(players: &[PlayerState]) -> Vec<&str> {
+        players.iter().map(|p| p.bus_name.as_str()).collect()
+    }
+
+    #[test]
+    fn clicking_the_volume_slider_sends_the_new_volume() {
+        let ctx = egui::Context::default();
+        let mut backend = FakeBackend::new(vec![fake_player("first", "First"), fake_player("second", "Second")]);
+        let mut panel = PlayerPanel::default();
+        panel.update(&mut backend);
+
+        let output = show_frame(&ctx, &mut panel, &mut backend, vec![]);
+        let label = text_rect(&output, "Volume").expect("No volume slider");
+
+        /* Near the left end of the slider, which follows its label */
+        let pos = egui::pos2(label.max.x + ctx.style().spacing.item_spacing.x + 4.0, label.center().y);
+        show_frame(&ctx, &mut panel, &mut backend, vec![egui::Event::PointerMoved(pos), click(pos, true)]);
+        show_frame(&ctx, &mut panel, &mut backend, vec![click(pos, false)]);
+
+        let commands = backend.take_commands();
+        assert_eq!(commands.len(), 1, "{:?}", commands);
+
+        let (bus_name, command) = &commands[0];
+        assert_eq!(bus_name, "org.mpris.MediaPlayer2.first");
+        let PlayerCommand::SetVolume(volume) = *command else { panic!("{:?} is not SetVolume", command) };
+        assert!(volume < 0.1, "{}", volume);
+
+        assert_eq!(backend.players()[0].volume, Some(volume));
+        assert_eq!(panel.selected_player().unwrap().volume, Some(volume));
+    }
+
+    #[test]
+    fn selection_follows_the_player_when_players_are_reordered() {
+        let mut backend = FakeBackend::new(vec![fake_player("a", "A"), fake_player("b", "B")]);
+        let mut panel = PlayerPanel::default();
+        panel.update(&mut backend);
+        panel.selected_player_id = 1;
+
+        backend.set_players(vec![fake_player("c", "C"), fake_player("b", "B"), fake_player("a", "A")]);
+        assert!(panel.update(&mut backend));
+        assert_eq!(panel.selected_player().unwrap().bus_name, "org.mpris.MediaPlayer2.b");
+
+        /* Nothing new to pick up */
+        assert!(!panel.update(&mut backend));
+
+        backend.set_players(vec![fake_player("a", "A")]);
+        panel.update(&mut backend);
+        assert_eq!(panel.selected_player().unwrap().bus_name, "org.mpris.MediaPlayer2.a");
+    }
+
+    #[test]
+    fn hidden_identities_are_left_out() {
+        let mut backend = FakeBackend::new(vec![
+            fake_player("a", "A"), fake_player("b", "B"), fake_player("c", "C"),
+        ]);
+        let mut panel = PlayerPanel::default();
+        panel.update(&mut backend);
+        panel.selected_player_id = 2;
+
+        panel.set_hidden(&["B".to_string()]);
+        assert_eq!(bus_names(&panel.players), ["org.mpris.MediaPlayer2.a", "org.mpris.MediaPlayer2.c"]);
+        assert_eq!(panel.selected_player().unwrap().bus_name, "org.mpris.MediaPlayer2.c");
+
+        /* The settings still list them, to show them again */
+        assert_eq!(panel.identities(), ["A", "B", "C"]);
+
+        backend.set_players(vec![fake_player("b", "B")]);
+        panel.update(&mut backend);
+        assert!(panel.selected_player().is_none());
+
+        panel.set_hidden(&[]);
+        assert_eq!(bus_names(&panel.players), ["org.mpris.MediaPlayer2.b"]);
+    }
+}
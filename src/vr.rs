@@ -0,0 +1,425 @@
+use openvr_sys2::{
+    VROverlayFlags,
+    VROverlayInputMethod::*,
+    EGamepadTextInputMode::*,
+    EGamepadTextInputLineMode::*,
+    EKeyboardFlags::*,
+};
+
+use egui_sdl2_gl::gl::types::GLuint;
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+use std::ffi::{c_char, CStr, CString};
+
+use crate::applications::{Applications, APP_KEY};
+use crate::cursor::{Cursor, CURSOR_WIDTH_METERS};
+
+/// The overlay and system calls the event loop makes, so that it can run
+/// against something other than a live SteamVR session.
+pub trait VrRuntime {
+    fn set_thumbnail(&mut self, path: &str);
+    fn set_input_method_mouse(&mut self);
+    fn set_flag(&mut self, flag: VROverlayFlags, enabled: bool);
+    fn set_mouse_scale(&mut self, scale: [f32; 2]);
+    fn set_width_in_meters(&mut self, width: f32);
+
+    /// Shows the whole of an OpenGL texture on the overlay.
+    fn submit_texture(&mut self, texture: GLuint);
+
+    fn poll_system_event(&mut self) -> Option<openvr_sys2::VREvent_t>;
+    fn poll_overlay_event(&mut self) -> Option<openvr_sys2::VREvent_t>;
+
+    /// Tells SteamVR that the overlay exits after `VREvent_Quit`, so that it
+    /// does not wait for it.
+    fn acknowledge_quit(&mut self);
+
+    /// Whether SteamVR is still running. It can go away without sending
+    /// `VREvent_Quit`, e.g. when it crashes.
+    fn is_running(&mut self) -> bool;
+
+    /// Blocks until the compositor is ready for the next frame.
+    fn wait_frame_sync(&mut self, timeout_ms: u32);
+
+    /// Waits up to `timeout_ms` for more events while nothing is drawn,
+    /// returning early once one arrives.
+    fn wait_events(&mut self, timeout_ms: u32);
+
+    /// Whether the overlay is the one showing in the dashboard.
+    fn is_visible(&mut self) -> bool;
+
+    /// Opens the single-line SteamVR keyboard, which types into the focused
+    /// text field through `VREvent_KeyboardCharInput` events. It starts out
+    /// empty rather than showing the field's text.
+    fn show_keyboard(&mut self);
+    fn hide_keyboard(&mut self);
+
+    /// Replaces the laser's cursor on the overlay, or restores SteamVR's
+    /// default one.
+    fn set_cursor(&mut self, cursor: Option<Cursor>);
+
+    /// Draws the cursor at `position` (in overlay mouse coordinates) instead
+    /// of where the laser hits.
+    fn set_cursor_position(&mut self, position: Option<[f32; 2]>);
+
+    /// Whether SteamVR should start the installed overlay along with itself.
+    fn set_autostart(&mut self, enabled: bool);
+}
+
+#[allow(non_snake_case)]
+pub fn VROverlayFlags_EnableControlBar() -> VROverlayFlags { unsafe { std::mem::transmute((1 << 23) as u32) } }
+
+#[allow(non_snake_case)]
+pub fn VROverlayFlags_EnableControlBarKeyboard() -> VROverlayFlags { unsafe { std::mem::transmute((1 << 24) as u32) } }
+
+#[allow(non_snake_case)]
+pub fn VROverlayFlags_EnableControlBarClose() -> VROverlayFlags { unsafe { std::mem::transmute((1 << 25) as u32) } }
+
+extern "C" {
+    fn VR_GetVRInitErrorAsEnglishDescription(error: openvr_sys2::EVRInitError) -> *const c_char;
+}
+
+/// Why OpenVR could not be initialized.
+#[derive(Clone, Copy, PartialEq)]
+pub struct InitError(openvr_sys2::EVRInitError);
+
+impl std::fmt::Display for InitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let description = unsafe { VR_GetVRInitErrorAsEnglishDescription(self.0) };
+        if description.is_null() {
+            write!(f, "{:?}", self.0)
+        }
+        else {
+            write!(f, "{}", unsafe { CStr::from_ptr(description) }.to_string_lossy())
+        }
+    }
+}
+
+impl std::fmt::Debug for InitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} ({:?})", self, self.0)
+    }
+}
+
+fn init(application_type: openvr_sys2::EVRApplicationType) -> Result<*mut openvr_sys2::IVRSystem, InitError> {
+    let mut error = openvr_sys2::EVRInitError::VRInitError_None;
+    let vr_system = unsafe { openvr_sys2::VR_Init(&mut error, application_type, std::ptr::null()) };
+
+    if vr_system.is_null() {
+        return Err(InitError(error));
+    }
+
+    Ok(vr_system)
+}
+
+fn overlay() -> std::pin::Pin<&'static mut openvr_sys2::IVROverlay> {
+    let overlay_raw = openvr_sys2::VROverlay();
+    if overlay_raw.is_null() {
+        panic!("Failed to obtain handle to VROverlay");
+    }
+    unsafe { std::pin::Pin::new_unchecked(&mut *overlay_raw) }
+}
+
+/// Longest text the SteamVR keyboard accepts.
+const KEYBOARD_MAX_LENGTH: u32 = 256;
+
+/// How long `wait_events` sleeps between checks for new events.
+const EVENT_WAIT_SLICE: Duration = Duration::from_millis(20);
+
+/// How often `is_running` looks for the SteamVR server.
+const RUNNING_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Whether a SteamVR server process exists. OpenVR has no call that tells
+/// once the server is gone, they just fail or return defaults.
+fn vrserver_running() -> bool {
+    /* Without /proc, it cannot be told apart from a running one */
+    let Ok(entries) = std::fs::read_dir("/proc") else { return true };
+
+    entries.flatten().any(|entry| {
+        std::fs::read_to_string(entry.path().join("comm")).is_ok_and(|comm| comm.trim_end() == "vrserver")
+    })
+}
+
+/// A dashboard overlay in the running SteamVR session. OpenVR is shut down
+/// when it is dropped.
+pub struct OpenVrRuntime {
+    vr_system: *mut openvr_sys2::IVRSystem,
+    overlay_handle: openvr_sys2::VROverlayHandle_t,
+    thumbnail_handle: openvr_sys2::VROverlayHandle_t,
+    key: String,
+    /// Overlays showing each cursor, created the first time it is needed.
+    cursors: HashMap<Cursor, openvr_sys2::VROverlayHandle_t>,
+    /// Events picked up by `wait_events`, handed out before polling OpenVR
+    /// again.
+    system_events: VecDeque<openvr_sys2::VREvent_t>,
+    overlay_events: VecDeque<openvr_sys2::VREvent_t>,
+    last_running_check: Instant,
+}
+
+impl OpenVrRuntime {
+    /// Starts SteamVR if it is not running yet.
+    pub fn init(key: &str, name: &str) -> Result<OpenVrRuntime, InitError> {
+        let vr_system = init(openvr_sys2::EVRApplicationType::VRApplication_Overlay)?;
+
+        let mut overlay_handle_slot   = std::mem::MaybeUninit::<openvr_sys2::VROverlayHandle_t>::uninit();
+        let mut thumbnail_handle_slot = std::mem::MaybeUninit::<openvr_sys2::VROverlayHandle_t>::uninit();
+        unsafe {
+            let key = CString::new(key).unwrap();
+            let name = CString::new(name).unwrap();
+
+            overlay().CreateDashboardOverlay(
+                key.as_ptr() as *const _,
+                name.as_ptr() as *const _,
+                overlay_handle_slot.as_mut_ptr(),
+                thumbnail_handle_slot.as_mut_ptr());
+        }
+
+        Ok(OpenVrRuntime {
+            vr_system,
+            overlay_handle: unsafe { overlay_handle_slot.assume_init() },
+            thumbnail_handle: unsafe { thumbnail_handle_slot.assume_init() },
+            key: key.to_string(),
+            cursors: HashMap::new(),
+            system_events: VecDeque::new(),
+            overlay_events: VecDeque::new(),
+            last_running_check: Instant::now(),
+        })
+    }
+
+    /// Like `init`, but fails instead of starting SteamVR.
+    pub fn init_if_running(key: &str, name: &str) -> Result<OpenVrRuntime, InitError> {
+        drop(BackgroundSession::init()?);
+        OpenVrRuntime::init(key, name)
+    }
+
+    fn next_system_event(&mut self) -> Option<openvr_sys2::VREvent_t> {
+        let mut event = std::mem::MaybeUninit::<openvr_sys2::VREvent_t>::uninit();
+
+        unsafe {
+            let vr = std::pin::Pin::new_unchecked(&mut *self.vr_system);
+            if vr.PollNextEvent(event.as_mut_ptr() as *mut _,
+                                std::mem::size_of::<openvr_sys2::VREvent_t>() as u32) {
+                return Some(event.assume_init());
+            }
+        }
+
+        None
+    }
+
+    fn next_overlay_event(&mut self) -> Option<openvr_sys2::VREvent_t> {
+        let mut event = std::mem::MaybeUninit::<openvr_sys2::VREvent_t>::uninit();
+
+        unsafe {
+            if overlay().PollNextOverlayEvent(
+                self.overlay_handle, event.as_mut_ptr() as *mut _,
+                std::mem::size_of::<openvr_sys2::VREvent_t>() as u32) {
+                return Some(event.assume_init());
+            }
+        }
+
+        None
+    }
+
+    fn cursor_overlay(&mut self, cursor: Cursor) -> Option<openvr_sys2::VROverlayHandle_t> {
+        if let Some(&handle) = self.cursors.get(&cursor) {
+            return Some(handle);
+        }
+
+        let image = image::load_from_memory(cursor.image()).ok()?.to_rgba8();
+
+        let key = CString::new(format!("{}.cursor.{}", self.key, cursor.name())).unwrap();
+        let mut handle_slot = std::mem::MaybeUninit::<openvr_sys2::VROverlayHandle_t>::uninit();
+
+        let handle = unsafe {
+            let error = overlay().CreateOverlay(
+                key.as_ptr() as *const _, key.as_ptr() as *const _, handle_slot.as_mut_ptr());
+            if error != openvr_sys2::EVROverlayError::VROverlayError_None {
+                return None;
+            }
+
+            let handle = handle_slot.assume_init();
+
+            let mut pixels = image.as_raw().clone();
+            overlay().SetOverlayRaw(handle, pixels.as_mut_ptr() as *mut _,
+                                    image.width(), image.height(), 4);
+            handle
+        };
+
+        overlay().SetOverlayWidthInMeters(handle, CURSOR_WIDTH_METERS);
+
+        self.cursors.insert(cursor, handle);
+        Some(handle)
+    }
+}
+
+impl Drop for OpenVrRuntime {
+    fn drop(&mut self) {
+        openvr_sys2::VR_Shutdown();
+    }
+}
+
+/// OpenVR initialized only to look at and change SteamVR's settings, next to
+/// the overlay if it runs. Fails rather than starting SteamVR.
+pub struct BackgroundSession(());
+
+impl BackgroundSession {
+    pub fn init() -> Result<BackgroundSession, InitError> {
+        init(openvr_sys2::EVRApplicationType::VRApplication_Background)?;
+        Ok(BackgroundSession(()))
+    }
+}
+
+impl Drop for BackgroundSession {
+    fn drop(&mut self) {
+        openvr_sys2::VR_Shutdown();
+    }
+}
+
+impl VrRuntime for OpenVrRuntime {
+    fn set_thumbnail(&mut self, path: &str) {
+        if let Ok(path) = CString::new(path) {
+            unsafe {
+                overlay().SetOverlayFromFile(self.thumbnail_handle, path.as_ptr());
+            }
+        }
+    }
+
+    fn set_input_method_mouse(&mut self) {
+        overlay().SetOverlayInputMethod(self.overlay_handle, VROverlayInputMethod_Mouse);
+    }
+
+    fn set_flag(&mut self, flag: VROverlayFlags, enabled: bool) {
+        overlay().SetOverlayFlag(self.overlay_handle, flag, enabled);
+    }
+
+    fn set_mouse_scale(&mut self, scale: [f32; 2]) {
+        unsafe {
+            overlay().SetOverlayMouseScale(self.overlay_handle, scale.as_ptr() as *const _);
+        }
+    }
+
+    fn set_width_in_meters(&mut self, width: f32) {
+        overlay().SetOverlayWidthInMeters(self.overlay_handle, width);
+    }
+
+    fn submit_texture(&mut self, texture: GLuint) {
+        let bounds = openvr_sys2::VRTextureBounds_t {
+            uMin: 0.0, vMin: 0.0, uMax: 1.0, vMax: 1.0
+        };
+
+        let texture = openvr_sys2::Texture_t {
+            eType: openvr_sys2::ETextureType::TextureType_OpenGL,
+            /* egui_sdl2_gl renders with FRAMEBUFFER_SRGB enabled */
+            eColorSpace: openvr_sys2::EColorSpace::ColorSpace_Gamma,
+            handle: texture as usize as *mut std::ffi::c_void
+        };
+
+        unsafe { overlay().SetOverlayTexture(self.overlay_handle, &texture); };
+        unsafe { overlay().SetOverlayTextureBounds(self.overlay_handle, &bounds); };
+    }
+
+    fn poll_system_event(&mut self) -> Option<openvr_sys2::VREvent_t> {
+        self.system_events.pop_front().or_else(|| self.next_system_event())
+    }
+
+    fn poll_overlay_event(&mut self) -> Option<openvr_sys2::VREvent_t> {
+        self.overlay_events.pop_front().or_else(|| self.next_overlay_event())
+    }
+
+    fn acknowledge_quit(&mut self) {
+        unsafe {
+            std::pin::Pin::new_unchecked(&mut *self.vr_system).AcknowledgeQuit_Exiting();
+        }
+    }
+
+    fn is_running(&mut self) -> bool {
+        if self.last_running_check.elapsed() < RUNNING_CHECK_INTERVAL {
+            return true;
+        }
+
+        self.last_running_check = Instant::now();
+        vrserver_running()
+    }
+
+    fn wait_frame_sync(&mut self, timeout_ms: u32) {
+        overlay().WaitFrameSync(timeout_ms);
+    }
+
+    fn wait_events(&mut self, timeout_ms: u32) {
+        /* OpenVR has nothing to block on until an event arrives, so check
+         * for one between short sleeps */
+        let deadline = Instant::now() + Duration::from_millis(timeout_ms as u64);
+
+        while self.system_events.is_empty() && self.overlay_events.is_empty() {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+
+            std::thread::sleep(EVENT_WAIT_SLICE.min(deadline - now));
+
+            while let Some(event) = self.next_system_event() {
+                self.system_events.push_back(event);
+            }
+            while let Some(event) = self.next_overlay_event() {
+                self.overlay_events.push_back(event);
+            }
+        }
+    }
+
+    fn is_visible(&mut self) -> bool {
+        overlay().IsOverlayVisible(self.overlay_handle)
+    }
+
+    fn show_keyboard(&mut self) {
+        let description = CString::default();
+        let text = CString::default();
+
+        unsafe {
+            overlay().ShowKeyboardForOverlay(
+                self.overlay_handle,
+                k_EGamepadTextInputModeNormal,
+                k_EGamepadTextInputLineModeSingleLine,
+                /* Only minimal mode sends every key as it is typed; otherwise
+                 * the text has to be read back with GetKeyboardText */
+                KeyboardFlag_Minimal as u32,
+                description.as_ptr(),
+                KEYBOARD_MAX_LENGTH,
+                text.as_ptr(),
+                0);
+        }
+    }
+
+    fn hide_keyboard(&mut self) {
+        overlay().HideKeyboard();
+    }
+
+    fn set_cursor(&mut self, cursor: Option<Cursor>) {
+        /* k_ulOverlayHandleInvalid brings back the default cursor */
+        let handle = cursor.and_then(|cursor| self.cursor_overlay(cursor)).unwrap_or(0);
+        overlay().SetOverlayCursor(self.overlay_handle, handle);
+    }
+
+    fn set_cursor_position(&mut self, position: Option<[f32; 2]>) {
+        match position {
+            Some(position) => unsafe {
+                overlay().SetOverlayCursorPositionOverride(
+                    self.overlay_handle, position.as_ptr() as *const _);
+            },
+            None => {
+                overlay().ClearOverlayCursorPositionOverride(self.overlay_handle);
+            },
+        }
+    }
+
+    fn set_autostart(&mut self, enabled: bool) {
+        let Some(applications) = Applications::get() else {
+            log::warn!("Failed to change autostart: IVRApplications is not available");
+            return;
+        };
+
+        if let Err(err) = applications.set_auto_launch(APP_KEY, enabled) {
+            log::warn!("Failed to change autostart (is the overlay installed?): {}", err);
+        }
+    }
+}